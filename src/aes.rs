@@ -0,0 +1,177 @@
+//! WinZip AES encryption
+use crate::random;
+use crate::sha1::{Hmac, pbkdf2};
+use std::io::{self, Write};
+
+/// a length of the authentication code following the content
+const AUTH_LEN: usize = 10;
+const VERIFIER_LEN: usize = 2;
+const ITERATIONS: u32 = 1000;
+const BLOCK_LEN: usize = 16;
+
+#[rustfmt::skip]
+static SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+/// a key length of AES strength 1-3
+fn key_len(strength: u8) -> usize {
+    8 + 8 * strength as usize
+}
+
+/// a length of the salt and the verifier preceding the content and the authentication code,
+/// the salt is a half of the key length
+pub(crate) fn overhead(strength: u8) -> u64 {
+    (key_len(strength) / 2 + VERIFIER_LEN + AUTH_LEN) as u64
+}
+
+/// multiplies by x in GF(2^8)
+fn xtime(b: u8) -> u8 {
+    (b << 1) ^ if b & 0x80 != 0 { 0x1B } else { 0 }
+}
+
+/// Encrypts blocks by AES-128, 192 or 256
+struct Cipher {
+    round_keys: Vec<[u8; BLOCK_LEN]>,
+}
+
+impl Cipher {
+    fn new(key: &[u8]) -> Self {
+        let nk = key.len() / 4;
+        let rounds = nk + 6;
+        let mut words: Vec<[u8; 4]> = key
+            .chunks_exact(4)
+            .map(|word| word.try_into().unwrap())
+            .collect();
+        let mut rcon = 1_u8;
+        for i in nk..4 * (rounds + 1) {
+            let mut temp = words[i - 1];
+            if i % nk == 0 {
+                temp = [
+                    SBOX[temp[1] as usize] ^ rcon,
+                    SBOX[temp[2] as usize],
+                    SBOX[temp[3] as usize],
+                    SBOX[temp[0] as usize],
+                ];
+                rcon = xtime(rcon)
+            } else if nk > 6 && i % nk == 4 {
+                temp = temp.map(|b| SBOX[b as usize])
+            }
+            let prev = words[i - nk];
+            words.push([0, 1, 2, 3].map(|j| prev[j] ^ temp[j]))
+        }
+        let round_keys = words
+            .chunks_exact(4)
+            .map(|round| round.concat().try_into().unwrap())
+            .collect();
+        Cipher { round_keys }
+    }
+
+    fn encrypt(&self, block: &mut [u8; BLOCK_LEN]) {
+        let last = self.round_keys.len() - 1;
+        add_round_key(block, &self.round_keys[0]);
+        for (round, key) in self.round_keys.iter().enumerate().skip(1) {
+            // sub bytes and shift rows, the state is in columns
+            let state = *block;
+            for (i, b) in block.iter_mut().enumerate() {
+                *b = SBOX[state[(i + 4 * (i % 4)) % BLOCK_LEN] as usize]
+            }
+            if round != last {
+                for column in block.chunks_exact_mut(4) {
+                    let all = column[0] ^ column[1] ^ column[2] ^ column[3];
+                    let first = column[0];
+                    for i in 0..4 {
+                        let next = if i == 3 { first } else { column[i + 1] };
+                        column[i] ^= all ^ xtime(column[i] ^ next)
+                    }
+                }
+            }
+            add_round_key(block, key)
+        }
+    }
+}
+
+fn add_round_key(block: &mut [u8; BLOCK_LEN], key: &[u8; BLOCK_LEN]) {
+    block.iter_mut().zip(key).for_each(|(b, k)| *b ^= k)
+}
+
+/// Encrypts everything written in the inner writer in the CTR mode and authenticates it
+pub(crate) struct Aes<'a> {
+    cipher: Cipher,
+    hmac: Hmac,
+    /// a little endian block counter
+    counter: u128,
+    keystream: [u8; BLOCK_LEN],
+    /// a number of used keystream bytes
+    used: usize,
+    inner: &'a mut dyn Write,
+    buf: Vec<u8>,
+}
+
+impl<'a> Aes<'a> {
+    /// writes the salt and the password verifier, so the writer is ready for the content
+    pub(crate) fn new(password: &str, strength: u8, inner: &'a mut dyn Write) -> io::Result<Self> {
+        let key_len = key_len(strength);
+        let mut salt = vec![0_u8; key_len / 2];
+        random::fill(&mut salt)?;
+        let mut keys = vec![0_u8; 2 * key_len + VERIFIER_LEN];
+        pbkdf2(password.as_bytes(), &salt, ITERATIONS, &mut keys);
+        inner.write_all(&salt)?;
+        inner.write_all(&keys[2 * key_len..])?;
+        Ok(Aes {
+            cipher: Cipher::new(&keys[..key_len]),
+            hmac: Hmac::new(&keys[key_len..2 * key_len]),
+            counter: 0,
+            keystream: [0; BLOCK_LEN],
+            used: BLOCK_LEN,
+            inner,
+            buf: vec![],
+        })
+    }
+
+    /// writes the authentication code after the content
+    pub(crate) fn finish(self) -> io::Result<()> {
+        self.inner.write_all(&self.hmac.finish()[..AUTH_LEN])
+    }
+}
+
+impl Write for Aes<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut encrypted = std::mem::take(&mut self.buf);
+        encrypted.clear();
+        for &b in buf {
+            if self.used == BLOCK_LEN {
+                self.counter += 1;
+                self.keystream = self.counter.to_le_bytes();
+                self.cipher.encrypt(&mut self.keystream);
+                self.used = 0
+            }
+            encrypted.push(b ^ self.keystream[self.used]);
+            self.used += 1
+        }
+        self.hmac.update(&encrypted);
+        // the keystream is already consumed, so all has to be written
+        let res = self.inner.write_all(&encrypted);
+        self.buf = encrypted;
+        res.map(|_| buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,409 @@
+//! Pure Rust bzip2 encoder
+use crate::codec::Encoder;
+use crate::huffman::code_lengths;
+use std::io::{self, Write};
+
+const BLOCK_MAGIC: u64 = 0x3141_5926_5359;
+const END_MAGIC: u64 = 0x1772_4538_5090;
+const RUNA: u16 = 0;
+const RUNB: u16 = 1;
+/// symbols coded by one Huffman table
+const GROUP_SIZE: usize = 50;
+const MAX_CODE_LEN: u8 = 17;
+const ITERATIONS: usize = 4;
+
+/// CRC32 of bzip2 is calculated from the most significant bit
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0_u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+            bit += 1
+        }
+        table[i] = crc;
+        i += 1
+    }
+    table
+};
+
+/// Writes bits starting from the most significant one
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u64, len: u32) {
+        self.bits = (self.bits << len) | (value & ((1 << len) - 1));
+        self.count += len;
+        while self.count >= 8 {
+            self.count -= 8;
+            self.out.push((self.bits >> self.count) as u8)
+        }
+    }
+
+    /// pads the last byte by zero bits
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.put(0, 8 - self.count)
+        }
+    }
+}
+
+/// Compresses a content coming by chunks in blocks of 100-900k
+pub(crate) struct Bzip2 {
+    /// a maximum length of a block after the initial run length encoding
+    block_max: usize,
+    level: u8,
+    /// the block after the initial run length encoding
+    block: Vec<u8>,
+    block_crc: u32,
+    combined_crc: u32,
+    run_byte: u8,
+    run_len: usize,
+    bits: BitWriter,
+    started: bool,
+}
+
+impl Bzip2 {
+    /// the level 1-9 sets a block size in 100k
+    pub(crate) fn new(level: u8) -> Self {
+        let level = level.clamp(1, 9);
+        let block_max = 100_000 * level as usize - 19;
+        Bzip2 {
+            block_max,
+            level,
+            block: Vec::with_capacity(block_max + 5),
+            block_crc: !0,
+            combined_crc: 0,
+            run_byte: 0,
+            run_len: 0,
+            bits: BitWriter::default(),
+            started: false,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.run_len > 0 && byte == self.run_byte && self.run_len < 255 {
+            self.run_len += 1
+        } else {
+            self.flush_run();
+            self.run_byte = byte;
+            self.run_len = 1
+        }
+    }
+
+    /// adds a run in the block as up to 4 bytes followed by a number of extra repeats
+    fn flush_run(&mut self) {
+        if self.run_len == 0 {
+            return;
+        }
+        if self.block.len() + 5 > self.block_max {
+            self.compress_block()
+        }
+        for _ in 0..self.run_len {
+            self.block_crc = (self.block_crc << 8)
+                ^ CRC_TABLE[((self.block_crc >> 24) as u8 ^ self.run_byte) as usize]
+        }
+        for _ in 0..self.run_len.min(4) {
+            self.block.push(self.run_byte)
+        }
+        if self.run_len >= 4 {
+            self.block.push((self.run_len - 4) as u8)
+        }
+        self.run_len = 0
+    }
+
+    fn start(&mut self) {
+        if !self.started {
+            self.bits.out.extend_from_slice(b"BZh");
+            self.bits.out.push(b'0' + self.level);
+            self.started = true
+        }
+    }
+
+    fn compress_block(&mut self) {
+        self.start();
+        if self.block.is_empty() {
+            return;
+        }
+        let block_crc = !self.block_crc;
+        self.combined_crc = self.combined_crc.rotate_left(1) ^ block_crc;
+        let (last, orig_ptr) = bwt(&self.block);
+        let mut in_use = [false; 256];
+        for &b in &self.block {
+            in_use[b as usize] = true
+        }
+        let mut seq = [0_u8; 256];
+        let mut in_use_num = 0;
+        for (b, _) in in_use.iter().enumerate().filter(|(_, used)| **used) {
+            seq[b] = in_use_num as u8;
+            in_use_num += 1
+        }
+        let symbols = mtf(&last, &seq, in_use_num);
+        let alpha_size = in_use_num + 2;
+
+        self.bits.put(BLOCK_MAGIC, 48);
+        self.bits.put(block_crc as u64, 32);
+        self.bits.put(0, 1); // not randomized
+        self.bits.put(orig_ptr as u64, 24);
+        for i in 0..16 {
+            self.bits
+                .put(in_use[i * 16..i * 16 + 16].contains(&true) as u64, 1)
+        }
+        for i in 0..16 {
+            if in_use[i * 16..i * 16 + 16].contains(&true) {
+                for &used in &in_use[i * 16..i * 16 + 16] {
+                    self.bits.put(used as u64, 1)
+                }
+            }
+        }
+        let (lengths, selectors) = tables(&symbols, alpha_size);
+        self.bits.put(lengths.len() as u64, 3);
+        self.bits.put(selectors.len() as u64, 15);
+        let mut order: Vec<u8> = (0..lengths.len() as u8).collect();
+        for &sel in &selectors {
+            let j = order.iter().position(|&t| t == sel).unwrap_or_default();
+            order[..=j].rotate_right(1);
+            self.bits.put((1 << (j + 1)) - 2, j as u32 + 1)
+        }
+        let mut codes = vec![];
+        for table in &lengths {
+            let mut curr = table[0];
+            self.bits.put(curr as u64, 5);
+            for &len in table {
+                while curr < len {
+                    self.bits.put(0b10, 2);
+                    curr += 1
+                }
+                while curr > len {
+                    self.bits.put(0b11, 2);
+                    curr -= 1
+                }
+                self.bits.put(0, 1)
+            }
+            codes.push(canonical_codes(table))
+        }
+        for (group, &sel) in symbols.chunks(GROUP_SIZE).zip(&selectors) {
+            let (len, code) = (&lengths[sel as usize], &codes[sel as usize]);
+            for &s in group {
+                self.bits
+                    .put(code[s as usize] as u64, len[s as usize] as u32)
+            }
+        }
+        self.block.clear();
+        self.block_crc = !0
+    }
+
+    /// writes completed bytes
+    fn flush(&mut self, out: &mut dyn Write) -> io::Result<u64> {
+        out.write_all(&self.bits.out)?;
+        let len = self.bits.out.len() as u64;
+        self.bits.out.clear();
+        Ok(len)
+    }
+}
+
+impl Encoder for Bzip2 {
+    fn encode(&mut self, chunk: &[u8], out: &mut dyn Write) -> io::Result<u64> {
+        let mut res = 0;
+        for &byte in chunk {
+            self.push(byte);
+            if self.bits.out.len() >= 64 * 1024 {
+                res += self.flush(out)?
+            }
+        }
+        Ok(res + self.flush(out)?)
+    }
+
+    fn finish(&mut self, out: &mut dyn Write) -> io::Result<u64> {
+        self.flush_run();
+        self.compress_block();
+        self.bits.put(END_MAGIC, 48);
+        self.bits.put(self.combined_crc as u64, 32);
+        self.bits.align();
+        self.flush(out)
+    }
+}
+
+/// Burrows-Wheeler transform sorting rotations by prefix doubling,
+/// returns the last column and the row of the original data
+fn bwt(data: &[u8]) -> (Vec<u8>, usize) {
+    let n = data.len();
+    let mut rank: Vec<u32> = data.iter().map(|&b| b as u32).collect();
+    let mut sa: Vec<u32> = (0..n as u32).collect();
+    sa.sort_by_key(|&i| data[i as usize]);
+    let mut tmp = vec![0_u32; n];
+    let mut keys = vec![0_u64; n];
+    let mut counts = vec![0_usize; n.max(256) + 1];
+    let mut k = 1;
+    while k < n {
+        // order by the second half rank is given by the current order shifted by k
+        for (dst, &i) in tmp.iter_mut().zip(&sa) {
+            let i = i as usize;
+            *dst = if i >= k { i - k } else { i + n - k } as u32
+        }
+        // stable counting sort by the first half rank
+        counts.fill(0);
+        for &r in &rank {
+            counts[r as usize + 1] += 1
+        }
+        for r in 1..counts.len() {
+            counts[r] += counts[r - 1]
+        }
+        for &i in &tmp {
+            let r = rank[i as usize] as usize;
+            sa[counts[r]] = i;
+            counts[r] += 1
+        }
+        // new ranks
+        for (i, key) in keys.iter_mut().enumerate() {
+            let second = if i + k < n { i + k } else { i + k - n };
+            *key = (rank[i] as u64) << 32 | rank[second] as u64
+        }
+        tmp[sa[0] as usize] = 0;
+        let mut classes = 0;
+        for w in 1..n {
+            let (a, b) = (sa[w - 1] as usize, sa[w] as usize);
+            if keys[a] != keys[b] {
+                classes += 1
+            }
+            tmp[b] = classes
+        }
+        std::mem::swap(&mut rank, &mut tmp);
+        if classes as usize == n - 1 {
+            break;
+        }
+        k *= 2
+    }
+    let mut orig_ptr = 0;
+    let last = sa
+        .iter()
+        .enumerate()
+        .map(|(row, &i)| {
+            if i == 0 {
+                orig_ptr = row
+            }
+            data[(i as usize + n - 1) % n]
+        })
+        .collect();
+    (last, orig_ptr)
+}
+
+/// Move to front transform with zero runs coded by RUNA and RUNB, ends by the end of block
+fn mtf(last: &[u8], seq: &[u8; 256], in_use_num: usize) -> Vec<u16> {
+    let mut order: Vec<u8> = (0..in_use_num).map(|s| s as u8).collect();
+    let mut res = Vec::with_capacity(last.len() + 1);
+    let mut zeros = 0_usize;
+    let put_zeros = |res: &mut Vec<u16>, mut zeros: usize| {
+        zeros -= 1;
+        loop {
+            res.push(if zeros & 1 == 1 { RUNB } else { RUNA });
+            if zeros < 2 {
+                break;
+            }
+            zeros = (zeros - 2) / 2
+        }
+    };
+    for &b in last {
+        let s = seq[b as usize];
+        if order[0] == s {
+            zeros += 1;
+            continue;
+        }
+        if zeros > 0 {
+            put_zeros(&mut res, zeros);
+            zeros = 0
+        }
+        let j = order.iter().position(|&o| o == s).unwrap_or_default();
+        order[..=j].rotate_right(1);
+        res.push(j as u16 + 1)
+    }
+    if zeros > 0 {
+        put_zeros(&mut res, zeros)
+    }
+    res.push(in_use_num as u16 + 1);
+    res
+}
+
+/// Builds Huffman tables and selects a table for every group of symbols,
+/// returns code lengths of the tables and the selectors
+fn tables(symbols: &[u16], alpha_size: usize) -> (Vec<Vec<u8>>, Vec<u8>) {
+    let groups = match symbols.len() {
+        0..200 => 2,
+        200..600 => 3,
+        600..1200 => 4,
+        1200..2400 => 5,
+        _ => 6,
+    };
+    let mut freq = vec![0_u32; alpha_size];
+    for &s in symbols {
+        freq[s as usize] += 1
+    }
+    // initial tables cover ranges of symbols with similar frequencies
+    let mut lengths = vec![vec![15_u8; alpha_size]; groups];
+    let mut remaining = symbols.len() as u32;
+    let mut start = 0;
+    for (t, table) in lengths.iter_mut().enumerate() {
+        let target = remaining / (groups - t) as u32;
+        let mut end = start;
+        let mut sum = 0;
+        while end < alpha_size && (sum < target || end == start) {
+            sum += freq[end];
+            end += 1
+        }
+        if t == groups - 1 {
+            end = alpha_size
+        }
+        table[start..end].fill(0);
+        remaining -= sum.min(remaining);
+        start = end
+    }
+    let mut selectors = vec![0_u8; symbols.len().div_ceil(GROUP_SIZE)];
+    for _ in 0..ITERATIONS {
+        let mut freqs = vec![vec![0_u32; alpha_size]; groups];
+        for (group, sel) in symbols.chunks(GROUP_SIZE).zip(selectors.iter_mut()) {
+            let best = (0..groups)
+                .min_by_key(|&t| {
+                    group
+                        .iter()
+                        .map(|&s| lengths[t][s as usize] as u32)
+                        .sum::<u32>()
+                })
+                .unwrap_or_default();
+            *sel = best as u8;
+            for &s in group {
+                freqs[best][s as usize] += 1
+            }
+        }
+        for (table, freq) in lengths.iter_mut().zip(&mut freqs) {
+            // every symbol has to have a code
+            freq.iter_mut().for_each(|f| *f = (*f << 8).max(1));
+            *table = code_lengths(freq, MAX_CODE_LEN)
+        }
+    }
+    (lengths, selectors)
+}
+
+/// Gives canonical Huffman codes
+fn canonical_codes(lengths: &[u8]) -> Vec<u32> {
+    let mut codes = vec![0; lengths.len()];
+    let mut code = 0_u32;
+    for len in 1..=MAX_CODE_LEN {
+        for (s, _) in lengths.iter().enumerate().filter(|(_, l)| **l == len) {
+            codes[s] = code;
+            code += 1
+        }
+        code <<= 1
+    }
+    codes
+}
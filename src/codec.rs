@@ -0,0 +1,120 @@
+//! Compression of a zip entry content supplied by chunks
+use crate::bzip2::Bzip2;
+#[cfg(any(feature = "rust_deflate", feature = "deflate"))]
+use crate::deflate::Deflater;
+use crate::lzma::Lzma;
+use crate::simzip::Compression;
+use crate::xz::Xz;
+use crate::zstd::{self, Zstd};
+#[cfg(feature = "deflate")]
+use libdeflater::{CompressionLvl, Compressor};
+use std::io::{self, Error, Write};
+
+/// a compression level used when neither an entry nor the zip set it
+pub(crate) static DEFAULT_LEVEL: u8 = 6;
+/// the maximum compression level libdeflate supports, zstd goes further
+pub(crate) static MAX_LEVEL: u8 = 12;
+
+/// Compresses a content coming by chunks
+pub(crate) trait Encoder {
+    /// compresses a next chunk, returns a number of bytes written in `out`
+    fn encode(&mut self, chunk: &[u8], out: &mut dyn Write) -> io::Result<u64>;
+
+    /// completes the compression, returns a number of bytes written in `out`
+    fn finish(&mut self, out: &mut dyn Write) -> io::Result<u64>;
+
+    /// compresses a whole content at once
+    fn encode_all(&mut self, data: &[u8], out: &mut dyn Write) -> io::Result<u64> {
+        let len = self.encode(data, out)?;
+        Ok(len + self.finish(out)?)
+    }
+}
+
+struct Store;
+
+impl Encoder for Store {
+    fn encode(&mut self, chunk: &[u8], out: &mut dyn Write) -> io::Result<u64> {
+        out.write_all(chunk)?;
+        Ok(chunk.len() as u64)
+    }
+
+    fn finish(&mut self, _out: &mut dyn Write) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+
+/// libdeflate has no streaming API, so it compresses whole contents in memory, while chunks
+/// of external files go through the streaming deflater keeping a memory use bounded
+#[cfg(feature = "deflate")]
+struct Deflate {
+    level: CompressionLvl,
+    /// created by a first chunk
+    streaming: Option<Deflater>,
+}
+
+#[cfg(feature = "deflate")]
+impl Deflate {
+    fn streaming(&mut self) -> &mut Deflater {
+        let level = self.level;
+        self.streaming
+            .get_or_insert_with(|| Deflater::new(i32::from(level) as u8))
+    }
+}
+
+#[cfg(feature = "deflate")]
+impl Encoder for Deflate {
+    fn encode(&mut self, chunk: &[u8], out: &mut dyn Write) -> io::Result<u64> {
+        self.streaming().encode(chunk, out)
+    }
+
+    fn finish(&mut self, out: &mut dyn Write) -> io::Result<u64> {
+        self.streaming().finish(out)
+    }
+
+    fn encode_all(&mut self, data: &[u8], out: &mut dyn Write) -> io::Result<u64> {
+        let mut compressor = Compressor::new(self.level);
+        let max_sz = compressor.deflate_compress_bound(data.len());
+        let mut compressed_data = vec![0; max_sz];
+        let actual_sz = compressor
+            .deflate_compress(data, &mut compressed_data)
+            .map_err(|e| Error::other(format!("because {e}")))?;
+        out.write_all(&compressed_data[..actual_sz])?;
+        Ok(actual_sz as u64)
+    }
+}
+
+/// the maximum compression level of the compression
+pub(crate) fn max_level(compression: &Compression) -> u8 {
+    match compression {
+        Compression::Zstd => zstd::MAX_LEVEL,
+        _ => MAX_LEVEL,
+    }
+}
+
+/// Gives an encoder for the compression with the level, Store ignores the level
+pub(crate) fn encoder(compression: &Compression, level: u8) -> io::Result<Box<dyn Encoder>> {
+    let max_level = max_level(compression);
+    if *compression != Compression::Store && level > max_level {
+        return Err(Error::other(
+            format! {"compression level {level} is out of 0-{max_level}"},
+        ));
+    }
+    match compression {
+        Compression::Store => Ok(Box::new(Store)),
+        #[cfg(feature = "deflate")]
+        Compression::Deflate => Ok(Box::new(Deflate {
+            level: CompressionLvl::new(level as _)
+                .map_err(|e| Error::other(format!("because {e:?}")))?,
+            streaming: None,
+        })),
+        #[cfg(all(feature = "rust_deflate", not(feature = "deflate")))]
+        Compression::Deflate => Ok(Box::new(Deflater::new(level))),
+        Compression::BZIP2 => Ok(Box::new(Bzip2::new(level))),
+        Compression::LZMA => Ok(Box::new(Lzma::new(level))),
+        Compression::Zstd => Ok(Box::new(Zstd::new(level))),
+        Compression::Xz => Ok(Box::new(Xz::new(level))),
+        _ => Err(Error::other(
+            format! {"compression {:?} isn't supported yet", compression},
+        )),
+    }
+}
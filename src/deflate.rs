@@ -0,0 +1,591 @@
+//! Pure Rust deflate (RFC 1951) encoder used when libdeflate isn't available, and for
+//! external files when it is, because libdeflate compresses only whole contents
+use crate::codec::Encoder;
+use crate::huffman::code_lengths;
+use std::io::{self, Write};
+
+/// a window matches can refer in
+const WINDOW: usize = 32 * 1024;
+/// input bytes compressed in one block
+const BLOCK: usize = 64 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+/// the maximum size of a stored block
+const MAX_STORED: usize = 0xFFFF;
+
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// an order code length code lengths are written in
+const CL_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Writes bits starting from the least significant one
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u32, len: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += len;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8
+        }
+    }
+
+    /// pads the last byte by zero bits
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+            self.bits = 0;
+            self.count = 0
+        }
+    }
+}
+
+/// Match search parameters of a compression level
+struct Params {
+    /// the maximum number of chain entries to check
+    chain: usize,
+    /// a match length after which the next position isn't checked for a longer match,
+    /// 0 means a greedy search
+    lazy: usize,
+    /// a match length stopping the search
+    nice: usize,
+}
+
+/// levels 1-9 are close to zlib, 10-12 extend the search, every parameter only grows with
+/// the level, so higher levels don't compress worse
+fn params(level: u8) -> Params {
+    let (chain, lazy, nice) = match level {
+        1 => (4, 0, 8),
+        2 => (8, 0, 16),
+        3 => (16, 0, 32),
+        4 => (32, 4, 32),
+        5 => (64, 16, 64),
+        6 => (128, 32, 128),
+        7 => (256, 64, 258),
+        8 => (1024, 128, 258),
+        9 => (4096, 258, 258),
+        10 => (8192, 258, 258),
+        11 => (16384, 258, 258),
+        _ => (32768, 258, 258),
+    };
+    Params { chain, lazy, nice }
+}
+
+/// A literal when `dist` is 0, otherwise a match
+#[derive(Clone, Copy)]
+struct Token {
+    len: u16,
+    dist: u16,
+}
+
+/// Deflates a content coming by chunks keeping only a window and one block in memory
+pub(crate) struct Deflater {
+    level: u8,
+    params: Params,
+    /// the window followed by not compressed yet data
+    buf: Vec<u8>,
+    /// an absolute position of `buf[0]`
+    base: u64,
+    /// an index in `buf` of not compressed yet data
+    start: usize,
+    /// last absolute positions + 1 of hashes
+    head: Vec<u64>,
+    /// previous absolute positions + 1 with the same hash
+    prev: Vec<u64>,
+    bits: BitWriter,
+}
+
+impl Deflater {
+    pub(crate) fn new(level: u8) -> Self {
+        Deflater {
+            level,
+            params: params(level),
+            buf: Vec::with_capacity(WINDOW + BLOCK + MAX_MATCH),
+            base: 0,
+            start: 0,
+            head: vec![0; 1 << HASH_BITS],
+            prev: vec![0; WINDOW],
+            bits: BitWriter::default(),
+        }
+    }
+
+    fn hash(&self, i: usize) -> usize {
+        let b = &self.buf[i..i + MIN_MATCH];
+        (((b[0] as usize) << 10) ^ ((b[1] as usize) << 5) ^ b[2] as usize) & ((1 << HASH_BITS) - 1)
+    }
+
+    fn insert(&mut self, i: usize) {
+        if i + MIN_MATCH <= self.buf.len() {
+            let h = self.hash(i);
+            let pos = self.base + i as u64;
+            self.prev[pos as usize % WINDOW] = self.head[h];
+            self.head[h] = pos + 1
+        }
+    }
+
+    /// finds the longest match for the position in `buf` not going over the end,
+    /// returns its (len, dist)
+    fn find_match(&self, i: usize, end: usize) -> (usize, usize) {
+        let max_len = MAX_MATCH.min(end - i);
+        let mut best = (0, 0);
+        if max_len < MIN_MATCH {
+            return best;
+        }
+        let pos = self.base + i as u64;
+        let mut candidate = self.head[self.hash(i)];
+        let mut chain = self.params.chain;
+        while candidate > 0 && chain > 0 {
+            let c = candidate - 1;
+            if c >= pos || pos - c > WINDOW as u64 || c < self.base {
+                break;
+            }
+            let j = (c - self.base) as usize;
+            let best_len = best.0;
+            if best_len == 0 || self.buf[j + best_len] == self.buf[i + best_len] {
+                let len = self.buf[j..j + max_len]
+                    .iter()
+                    .zip(&self.buf[i..i + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                let dist = (pos - c) as usize;
+                // a longer match is worth its farther distance extra bits
+                if len > best_len
+                    && (best_len == 0
+                        || (len - best_len) * 6 > dist_bits(dist).saturating_sub(dist_bits(best.1)))
+                {
+                    best = (len, dist);
+                    if len >= self.params.nice || len == max_len {
+                        break;
+                    }
+                }
+            }
+            let next = self.prev[c as usize % WINDOW];
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+            chain -= 1
+        }
+        if best.0 < MIN_MATCH { (0, 0) } else { best }
+    }
+
+    /// compresses `len` bytes from `start` in one block, levels over 9 keep the smallest of
+    /// blocks made by parameters of levels from 9 to the level
+    fn block(&mut self, len: usize, last: bool) {
+        let end = self.start + len;
+        let tokens = if self.level > 9 {
+            let (head, prev) = (self.head.clone(), self.prev.clone());
+            let mut best: Option<(u64, Vec<Token>)> = None;
+            for level in 9..=self.level {
+                self.head.copy_from_slice(&head);
+                self.prev.copy_from_slice(&prev);
+                self.params = params(level);
+                let tokens = self.tokens(end);
+                let bits = self.block_bits(&tokens, end, last);
+                if best.as_ref().is_none_or(|(best_bits, _)| bits < *best_bits) {
+                    best = Some((bits, tokens))
+                }
+            }
+            best.unwrap().1
+        } else {
+            self.tokens(end)
+        };
+        self.write_block(&tokens, self.start, end, last);
+        self.start = end;
+        // keep only the window
+        if self.start > WINDOW {
+            let shift = self.start - WINDOW;
+            self.buf.drain(..shift);
+            self.base += shift as u64;
+            self.start = WINDOW
+        }
+    }
+
+    /// splits data from `start` to the end to literals and matches
+    fn tokens(&mut self, end: usize) -> Vec<Token> {
+        let mut tokens = Vec::with_capacity(end - self.start);
+        if self.level == 0 {
+            return tokens;
+        }
+        let mut i = self.start;
+        let mut next: Option<(usize, usize)> = None;
+        while i < end {
+            let (len, dist) = next.take().unwrap_or_else(|| self.find_match(i, end));
+            self.insert(i);
+            if len == 0 {
+                tokens.push(Token {
+                    len: self.buf[i] as u16,
+                    dist: 0,
+                });
+                i += 1;
+                continue;
+            }
+            if len < self.params.lazy && i + 1 < end {
+                let following = self.find_match(i + 1, end);
+                if following.0 > len {
+                    tokens.push(Token {
+                        len: self.buf[i] as u16,
+                        dist: 0,
+                    });
+                    i += 1;
+                    next = Some(following);
+                    continue;
+                }
+            }
+            tokens.push(Token {
+                len: len as u16,
+                dist: dist as u16,
+            });
+            for j in i + 1..i + len {
+                self.insert(j)
+            }
+            i += len
+        }
+        tokens
+    }
+
+    /// a size in bits of the block of the tokens, nothing is written
+    fn block_bits(&mut self, tokens: &[Token], end: usize, last: bool) -> u64 {
+        let (len, bits, count) = (self.bits.out.len(), self.bits.bits, self.bits.count);
+        self.write_block(tokens, self.start, end, last);
+        let res = (self.bits.out.len() * 8) as u64 + self.bits.count as u64
+            - (len * 8) as u64
+            - count as u64;
+        self.bits.out.truncate(len);
+        (self.bits.bits, self.bits.count) = (bits, count);
+        res
+    }
+
+    /// writes the tokens of data in `buf` from `from` to `to` using the cheapest block type
+    fn write_block(&mut self, tokens: &[Token], from: usize, to: usize, last: bool) {
+        let stored_cost = ((to - from) as u64 + 5 * ((to - from) / MAX_STORED + 1) as u64) * 8 + 7;
+        if self.level == 0 {
+            return self.write_stored(from, to, last);
+        }
+        let mut lit_freq = [0_u32; 286];
+        let mut dist_freq = [0_u32; 30];
+        for token in tokens {
+            if token.dist == 0 {
+                lit_freq[token.len as usize] += 1
+            } else {
+                lit_freq[257 + len_code(token.len)] += 1;
+                dist_freq[dist_code(token.dist)] += 1
+            }
+        }
+        lit_freq[256] = 1;
+        let fixed_lit = fixed_lit_lengths();
+        let fixed_dist = [5_u8; 30];
+        let fixed_cost = 3 + data_cost(&lit_freq, &dist_freq, &fixed_lit, &fixed_dist);
+
+        let lit_len = code_lengths(&lit_freq, 15);
+        let mut dist_len = code_lengths(&dist_freq, 15);
+        if dist_len.iter().all(|&l| l == 0) {
+            // at least one distance code has to be present
+            dist_len[0] = 1
+        }
+        let hlit = 257.max(lit_len.iter().rposition(|&l| l > 0).unwrap_or(0) + 1);
+        let hdist = 1.max(dist_len.iter().rposition(|&l| l > 0).unwrap_or(0) + 1);
+        let mut all = lit_len[..hlit].to_vec();
+        all.extend_from_slice(&dist_len[..hdist]);
+        let cl_tokens = rle_lengths(&all);
+        let mut cl_freq = [0_u32; 19];
+        for &(sym, _) in &cl_tokens {
+            cl_freq[sym as usize] += 1
+        }
+        let cl_len = code_lengths(&cl_freq, 7);
+        let hclen = 4.max(CL_ORDER.iter().rposition(|&s| cl_len[s] > 0).unwrap_or(0) + 1);
+        let mut dynamic_cost = 3 + 5 + 5 + 4 + 3 * hclen as u64;
+        for &(sym, _) in &cl_tokens {
+            dynamic_cost += cl_len[sym as usize] as u64
+                + match sym {
+                    16 => 2,
+                    17 => 3,
+                    18 => 7,
+                    _ => 0,
+                }
+        }
+        dynamic_cost += data_cost(&lit_freq, &dist_freq, &lit_len, &dist_len);
+
+        if stored_cost <= fixed_cost.min(dynamic_cost) {
+            self.write_stored(from, to, last)
+        } else if fixed_cost <= dynamic_cost {
+            self.bits.put(last as u32, 1);
+            self.bits.put(0b01, 2);
+            self.write_tokens(tokens, &fixed_lit, &fixed_dist)
+        } else {
+            self.bits.put(last as u32, 1);
+            self.bits.put(0b10, 2);
+            self.bits.put((hlit - 257) as u32, 5);
+            self.bits.put((hdist - 1) as u32, 5);
+            self.bits.put((hclen - 4) as u32, 4);
+            for &s in &CL_ORDER[..hclen] {
+                self.bits.put(cl_len[s] as u32, 3)
+            }
+            let cl_codes = canonical_codes(&cl_len);
+            for &(sym, extra) in &cl_tokens {
+                self.bits
+                    .put(cl_codes[sym as usize] as u32, cl_len[sym as usize] as u32);
+                match sym {
+                    16 => self.bits.put(extra as u32, 2),
+                    17 => self.bits.put(extra as u32, 3),
+                    18 => self.bits.put(extra as u32, 7),
+                    _ => (),
+                }
+            }
+            self.write_tokens(tokens, &lit_len, &dist_len)
+        }
+    }
+
+    fn write_stored(&mut self, from: usize, to: usize, last: bool) {
+        let mut from = from;
+        loop {
+            let len = (to - from).min(MAX_STORED);
+            let final_part = last && from + len == to;
+            self.bits.put(final_part as u32, 1);
+            self.bits.put(0b00, 2);
+            self.bits.align();
+            self.bits.out.extend_from_slice(&(len as u16).to_le_bytes());
+            self.bits
+                .out
+                .extend_from_slice(&(!(len as u16)).to_le_bytes());
+            self.bits.out.extend_from_slice(&self.buf[from..from + len]);
+            from += len;
+            if from == to {
+                break;
+            }
+        }
+    }
+
+    fn write_tokens(&mut self, tokens: &[Token], lit_len: &[u8], dist_len: &[u8]) {
+        let lit_codes = canonical_codes(lit_len);
+        let dist_codes = canonical_codes(dist_len);
+        for token in tokens {
+            if token.dist == 0 {
+                let lit = token.len as usize;
+                self.bits.put(lit_codes[lit] as u32, lit_len[lit] as u32)
+            } else {
+                let code = len_code(token.len);
+                self.bits
+                    .put(lit_codes[257 + code] as u32, lit_len[257 + code] as u32);
+                self.bits
+                    .put((token.len - LEN_BASE[code]) as u32, LEN_EXTRA[code] as u32);
+                let code = dist_code(token.dist);
+                self.bits
+                    .put(dist_codes[code] as u32, dist_len[code] as u32);
+                self.bits.put(
+                    (token.dist - DIST_BASE[code]) as u32,
+                    DIST_EXTRA[code] as u32,
+                )
+            }
+        }
+        self.bits.put(lit_codes[256] as u32, lit_len[256] as u32)
+    }
+
+    /// writes completed bytes
+    fn flush(&mut self, out: &mut dyn Write) -> io::Result<u64> {
+        out.write_all(&self.bits.out)?;
+        let len = self.bits.out.len() as u64;
+        self.bits.out.clear();
+        Ok(len)
+    }
+}
+
+impl Encoder for Deflater {
+    fn encode(&mut self, mut chunk: &[u8], out: &mut dyn Write) -> io::Result<u64> {
+        let mut res = 0;
+        while !chunk.is_empty() {
+            // positions at the block end are hashed with following bytes
+            let room = (self.start + BLOCK + MAX_MATCH).saturating_sub(self.buf.len());
+            let part = room.min(chunk.len());
+            self.buf.extend_from_slice(&chunk[..part]);
+            chunk = &chunk[part..];
+            if self.buf.len() - self.start >= BLOCK + MAX_MATCH {
+                self.block(BLOCK, false);
+                res += self.flush(out)?
+            }
+        }
+        Ok(res)
+    }
+
+    fn finish(&mut self, out: &mut dyn Write) -> io::Result<u64> {
+        let mut res = 0;
+        while self.buf.len() - self.start > BLOCK {
+            self.block(BLOCK, false);
+            res += self.flush(out)?
+        }
+        let len = self.buf.len() - self.start;
+        self.block(len, true);
+        self.bits.align();
+        Ok(res + self.flush(out)?)
+    }
+}
+
+fn dist_bits(dist: usize) -> usize {
+    DIST_EXTRA[dist_code(dist as u16)] as usize
+}
+
+fn len_code(len: u16) -> usize {
+    LEN_BASE.partition_point(|&base| base <= len) - 1
+}
+
+fn dist_code(dist: u16) -> usize {
+    DIST_BASE.partition_point(|&base| base <= dist) - 1
+}
+
+fn fixed_lit_lengths() -> [u8; 288] {
+    let mut lengths = [8_u8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths
+}
+
+/// a number of bits the data encoded with the code lengths takes
+fn data_cost(lit_freq: &[u32], dist_freq: &[u32], lit_len: &[u8], dist_len: &[u8]) -> u64 {
+    let mut cost = 0;
+    for (i, &freq) in lit_freq.iter().enumerate() {
+        let extra = if i > 256 { LEN_EXTRA[i - 257] } else { 0 };
+        cost += freq as u64 * (lit_len[i] + extra) as u64
+    }
+    for (i, &freq) in dist_freq.iter().enumerate() {
+        cost += freq as u64 * (dist_len[i] + DIST_EXTRA[i]) as u64
+    }
+    cost
+}
+
+/// Gives canonical Huffman codes with reversed bits for writing from the least significant bit
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut counts = [0_u16; 16];
+    for &len in lengths {
+        counts[len as usize] += 1
+    }
+    counts[0] = 0;
+    let mut next = [0_u16; 16];
+    let mut code = 0_u16;
+    for len in 1..16 {
+        code = (code + counts[len - 1]) << 1;
+        next[len] = code
+    }
+    lengths
+        .iter()
+        .map(|&len| {
+            if len == 0 {
+                return 0;
+            }
+            let code = next[len as usize];
+            next[len as usize] += 1;
+            code.reverse_bits() >> (16 - len)
+        })
+        .collect()
+}
+
+/// Run length encodes code lengths to (symbol, extra bits value)
+fn rle_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut res = vec![];
+    let mut i = 0;
+    while i < lengths.len() {
+        let len = lengths[i];
+        let run = lengths[i..].iter().take_while(|&&l| l == len).count();
+        let mut left = run;
+        if len == 0 {
+            while left >= 11 {
+                let n = left.min(138);
+                res.push((18, (n - 11) as u8));
+                left -= n
+            }
+            if left >= 3 {
+                res.push((17, (left - 3) as u8));
+                left = 0
+            }
+        } else {
+            res.push((len, 0));
+            left -= 1;
+            while left >= 3 {
+                let n = left.min(6);
+                res.push((16, (n - 3) as u8));
+                left -= n
+            }
+        }
+        for _ in 0..left {
+            res.push((len, 0))
+        }
+        i += run
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// text, records and noise of a fixed generator
+    fn corpus() -> Vec<u8> {
+        let words = [
+            "zip", "entry", "deflate", "the", "of", "level", "a", "file", "header", "time",
+        ];
+        let mut seed = 1_u32;
+        let mut next = move || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 16) as usize
+        };
+        let mut res = vec![];
+        while res.len() < 400_000 {
+            match next() % 3 {
+                0 => {
+                    for _ in 0..200 {
+                        res.extend_from_slice(words[next() % words.len()].as_bytes());
+                        res.push(b' ')
+                    }
+                }
+                1 => {
+                    for i in 0..100 {
+                        let record = (i * 1000 + next() % 16) as u32;
+                        res.extend_from_slice(&record.to_le_bytes())
+                    }
+                }
+                _ => res.extend((0..400).map(|_| next() as u8)),
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn higher_levels_are_not_larger() {
+        let data = corpus();
+        let mut prev = usize::MAX;
+        for level in 1..=12 {
+            let mut deflater = Deflater::new(level);
+            let mut out = vec![];
+            deflater.encode(&data, &mut out).unwrap();
+            deflater.finish(&mut out).unwrap();
+            assert!(
+                out.len() <= prev,
+                "level {level} is larger than {}",
+                level - 1
+            );
+            prev = out.len()
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Matching of glob patterns as `*.rs`, `src/**/test?.txt`, or `[a-z]*`
+//!
+//! A pattern without a slash matches a name at any level, a pattern with a leading or a middle
+//! slash matches from the base, `**` matches any number of directories, a trailing one matches
+//! everything inside a directory but not the directory, other wildcards don't match a slash
+
+/// checks if a relative path using slashes matches the pattern
+pub(crate) fn matches(pattern: &str, path: &str) -> bool {
+    let path: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    // a leading slash only anchors the pattern
+    if pattern.contains('/') {
+        let pattern: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+        matches_segments(&pattern, &path)
+    } else {
+        path.last().is_some_and(|name| matches_name(pattern, name))
+    }
+}
+
+fn matches_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", [])) => !path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| matches_segments(rest, &path[skip..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path)) => matches_name(first, name) && matches_segments(rest, path),
+            None => false,
+        },
+    }
+}
+
+/// matches a single name supporting `*`, `?`, `[...]`, and `\` escaping
+fn matches_name(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_chars(&pattern, &name)
+}
+
+fn matches_chars(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| matches_chars(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && matches_chars(rest, &name[1..]),
+        Some(('[', rest)) => match (class(rest), name.split_first()) {
+            (Some((matched, rest)), Some((c, name))) => matched(*c) && matches_chars(rest, name),
+            (None, Some((c, name))) => *c == '[' && matches_chars(rest, name),
+            (_, None) => false,
+        },
+        Some(('\\', [escaped, rest @ ..])) | Some((escaped, rest)) => {
+            name.first() == Some(escaped) && matches_chars(rest, &name[1..])
+        }
+    }
+}
+
+/// parses a character class after `[`, returns its matcher and the rest of the pattern,
+/// or None when the class isn't closed
+fn class(pattern: &[char]) -> Option<(impl Fn(char) -> bool, &[char])> {
+    let (negated, pattern) = match pattern.split_first() {
+        Some(('!' | '^', rest)) => (true, rest),
+        _ => (false, pattern),
+    };
+    // a leading `]` is a member
+    let end = 1 + pattern.get(1..)?.iter().position(|&c| c == ']')?;
+    let (members, rest) = (&pattern[..end], &pattern[end + 1..]);
+    let matcher = move |c: char| {
+        let mut i = 0;
+        let mut res = false;
+        while i < members.len() {
+            if i + 2 < members.len() && members[i + 1] == '-' {
+                res |= (members[i]..=members[i + 2]).contains(&c);
+                i += 3
+            } else {
+                res |= members[i] == c;
+                i += 1
+            }
+        }
+        res != negated
+    };
+    Some((matcher, rest))
+}
@@ -0,0 +1,74 @@
+//! Huffman codes construction shared by encoders
+
+/// Calculates Huffman code lengths not exceeding the limit
+pub(crate) fn code_lengths(freqs: &[u32], limit: u8) -> Vec<u8> {
+    let mut lengths = vec![0_u8; freqs.len()];
+    let mut symbols: Vec<usize> = (0..freqs.len()).filter(|&s| freqs[s] > 0).collect();
+    match symbols.len() {
+        0 => return lengths,
+        1 => {
+            // a second code keeps the code complete
+            lengths[symbols[0]] = 1;
+            lengths[if symbols[0] == 0 { 1 } else { 0 }] = 1;
+            return lengths;
+        }
+        _ => (),
+    }
+    symbols.sort_by_key(|&s| freqs[s]);
+    let n = symbols.len();
+    // two queues method, leaves and then nodes are sorted by weight
+    let mut weight: Vec<u64> = symbols.iter().map(|&s| freqs[s] as u64).collect();
+    let mut parent = vec![0_usize; 2 * n - 1];
+    let (mut leaf, mut node) = (0, n);
+    for new in n..2 * n - 1 {
+        let mut pair = [0; 2];
+        for smallest in &mut pair {
+            if leaf < n && (node >= new || weight[leaf] <= weight[node]) {
+                *smallest = leaf;
+                leaf += 1
+            } else {
+                *smallest = node;
+                node += 1
+            }
+        }
+        weight.push(weight[pair[0]] + weight[pair[1]]);
+        parent[pair[0]] = new;
+        parent[pair[1]] = new
+    }
+    let mut depth = vec![0_usize; 2 * n - 1];
+    let mut counts = [0_u32; 64];
+    for i in (0..2 * n - 2).rev() {
+        depth[i] = depth[parent[i]] + 1;
+        if i < n {
+            counts[depth[i].min(63)] += 1
+        }
+    }
+    // move codes over the limit up the way the code stays complete
+    let limit = limit as usize;
+    for i in limit + 1..64 {
+        counts[limit] += counts[i];
+        counts[i] = 0
+    }
+    let mut total: u64 = (1..=limit).map(|i| (counts[i] as u64) << (limit - i)).sum();
+    while total > 1 << limit {
+        counts[limit] -= 1;
+        for i in (1..limit).rev() {
+            if counts[i] > 0 {
+                counts[i] -= 1;
+                counts[i + 1] += 2;
+                break;
+            }
+        }
+        total -= 1
+    }
+    // the least frequent symbols get the longest codes
+    let mut symbols = symbols.into_iter();
+    for len in (1..=limit).rev() {
+        for _ in 0..counts[len] {
+            if let Some(s) = symbols.next() {
+                lengths[s] = len as u8
+            }
+        }
+    }
+    lengths
+}
@@ -0,0 +1,107 @@
+//! Rules of ignore files in the .gitignore format
+use crate::glob;
+use std::{fs, io, path::Path};
+
+struct Rule {
+    pattern: String,
+    /// `!` re-includes what previous rules ignored
+    negated: bool,
+    /// a trailing slash matches only directories
+    dir_only: bool,
+}
+
+/// Rules of ignore files of a directory, they apply to paths below it
+pub(crate) struct Ignore {
+    /// the directory relative to the added root
+    base: String,
+    rules: Vec<Rule>,
+}
+
+impl Ignore {
+    /// reads the ignore files existing in the directory, None when there are no rules
+    pub(crate) fn read(dir: &Path, base: &str, names: &[String]) -> io::Result<Option<Self>> {
+        let mut rules = vec![];
+        for name in names {
+            let content = match fs::read_to_string(dir.join(name)) {
+                Ok(content) => content,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            rules.extend(content.lines().filter_map(parse))
+        }
+        Ok((!rules.is_empty()).then(|| Ignore {
+            base: base.to_string(),
+            rules,
+        }))
+    }
+
+    /// Some(true) when the last matching rule ignores the path, None when no rule matches
+    fn matched(&self, rel_path: &str, is_dir: bool) -> Option<bool> {
+        let path = if self.base.is_empty() {
+            rel_path
+        } else {
+            rel_path.strip_prefix(&self.base)?.strip_prefix('/')?
+        };
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && glob::matches(&rule.pattern, path))
+            .map(|rule| !rule.negated)
+    }
+}
+
+/// checks the path against rules of all levels, deeper rules take precedence
+pub(crate) fn ignored(ignores: &[Ignore], rel_path: &str, is_dir: bool) -> bool {
+    ignores
+        .iter()
+        .rev()
+        .find_map(|ignore| ignore.matched(rel_path, is_dir))
+        .unwrap_or(false)
+}
+
+fn parse(line: &str) -> Option<Rule> {
+    // trailing spaces are ignored unless escaped, other escapes are resolved by matching
+    let trimmed = line.trim_end_matches(' ');
+    let line = if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+        &line[..trimmed.len() + 1]
+    } else {
+        trimmed
+    };
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (dir_only, pattern) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    (!pattern.is_empty()).then(|| Rule {
+        pattern: pattern.to_string(),
+        negated,
+        dir_only,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignore(lines: &str) -> Ignore {
+        Ignore {
+            base: String::new(),
+            rules: lines.lines().filter_map(parse).collect(),
+        }
+    }
+
+    #[test]
+    fn negation_inside_ignored_contents() {
+        let ignores = [ignore("build/**\n!build/keep.txt")];
+        assert!(!ignored(&ignores, "build", true));
+        assert!(!ignored(&ignores, "build/keep.txt", false));
+        assert!(ignored(&ignores, "build/out.o", false));
+        assert!(ignored(&ignores, "build/sub", true));
+    }
+}
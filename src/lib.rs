@@ -1,6 +1,21 @@
 mod simzip;
+mod bzip2;
+mod codec;
+#[cfg(any(feature = "rust_deflate", feature = "deflate"))]
+mod deflate;
+mod huffman;
+mod lzma;
+mod zstd;
+mod xz;
+mod glob;
+mod ignore;
+mod tz;
+mod zipcrypto;
+mod aes;
+mod random;
+mod sha1;
 mod crc32;
 mod crctabl;
-pub use simzip::{ZipInfo, ZipEntry, Attribute, Compression};
+pub use simzip::{ZipInfo, ZipEntry, Attribute, Compression, DirOptions, DosTime, Encryption, Ownership, StorePolicy, Symlinks};
 pub const VERSION: &str = env!("VERSION");
 extern crate simtime;
\ No newline at end of file
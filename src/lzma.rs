@@ -0,0 +1,585 @@
+//! Pure Rust LZMA encoder
+use crate::codec::Encoder;
+use std::io::{self, Write};
+
+const MIN_MATCH: usize = 2;
+const MAX_MATCH: usize = 273;
+const HASH_BITS: u32 = 18;
+const STATES: usize = 12;
+/// literal context bits
+const LC: u32 = 3;
+/// position bits
+const PB: u32 = 2;
+const POS_STATES: usize = 1 << PB;
+const PROB_INIT: u16 = 1 << 10;
+const BIT_MODEL_TOTAL: u32 = 1 << 11;
+const MOVE_BITS: u32 = 5;
+const END_POS_MODEL: usize = 14;
+const FULL_DISTANCES: usize = 128;
+const ALIGN_BITS: u32 = 4;
+/// the version of LZMA SDK written in the zip header
+const SDK_VERSION: [u8; 2] = [9, 20];
+
+/// LZMA properties byte for lc=3, lp=0, pb=2
+pub(crate) const PROPS: u8 = ((PB * 5) * 9 + LC) as u8;
+
+/// Encodes bits with probabilities
+#[derive(Default)]
+pub(crate) struct RangeEncoder {
+    low: u64,
+    range: u32,
+    cache: u8,
+    cache_size: u64,
+    pub(crate) out: Vec<u8>,
+}
+
+impl RangeEncoder {
+    pub(crate) fn new() -> Self {
+        RangeEncoder {
+            range: 0xFFFF_FFFF,
+            cache_size: 1,
+            ..Default::default()
+        }
+    }
+
+    fn shift_low(&mut self) {
+        if (self.low as u32) < 0xFF00_0000 || (self.low >> 32) != 0 {
+            let carry = (self.low >> 32) as u8;
+            let mut temp = self.cache;
+            loop {
+                self.out.push(temp.wrapping_add(carry));
+                temp = 0xFF;
+                self.cache_size -= 1;
+                if self.cache_size == 0 {
+                    break;
+                }
+            }
+            self.cache = (self.low >> 24) as u8
+        }
+        self.cache_size += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8
+    }
+
+    fn bit(&mut self, prob: &mut u16, bit: u32) {
+        let bound = (self.range >> 11) * *prob as u32;
+        if bit == 0 {
+            self.range = bound;
+            *prob += ((BIT_MODEL_TOTAL - *prob as u32) >> MOVE_BITS) as u16
+        } else {
+            self.low += bound as u64;
+            self.range -= bound;
+            *prob -= *prob >> MOVE_BITS
+        }
+        while self.range < 1 << 24 {
+            self.range <<= 8;
+            self.shift_low()
+        }
+    }
+
+    fn direct(&mut self, value: u32, bits: u32) {
+        for i in (0..bits).rev() {
+            self.range >>= 1;
+            if (value >> i) & 1 == 1 {
+                self.low += self.range as u64
+            }
+            while self.range < 1 << 24 {
+                self.range <<= 8;
+                self.shift_low()
+            }
+        }
+    }
+
+    fn tree(&mut self, probs: &mut [u16], bits: u32, value: u32) {
+        let mut m = 1;
+        for i in (0..bits).rev() {
+            let bit = (value >> i) & 1;
+            self.bit(&mut probs[m], bit);
+            m = (m << 1) | bit as usize
+        }
+    }
+
+    fn reverse_tree(&mut self, probs: &mut [u16], bits: u32, mut value: u32) {
+        let mut m = 1;
+        for _ in 0..bits {
+            let bit = value & 1;
+            value >>= 1;
+            self.bit(&mut probs[m], bit);
+            m = (m << 1) | bit as usize
+        }
+    }
+
+    /// writes remaining bytes, the encoder can't be used after
+    pub(crate) fn flush(&mut self) {
+        for _ in 0..5 {
+            self.shift_low()
+        }
+    }
+
+    /// a number of bytes the encoder gives when flushed
+    pub(crate) fn pending(&self) -> usize {
+        self.out.len() + self.cache_size as usize + 4
+    }
+}
+
+#[derive(Clone)]
+struct LenEncoder {
+    choice: u16,
+    choice2: u16,
+    low: [[u16; 8]; POS_STATES],
+    mid: [[u16; 8]; POS_STATES],
+    high: [u16; 256],
+}
+
+impl LenEncoder {
+    fn new() -> Self {
+        LenEncoder {
+            choice: PROB_INIT,
+            choice2: PROB_INIT,
+            low: [[PROB_INIT; 8]; POS_STATES],
+            mid: [[PROB_INIT; 8]; POS_STATES],
+            high: [PROB_INIT; 256],
+        }
+    }
+
+    fn encode(&mut self, rc: &mut RangeEncoder, len: usize, pos_state: usize) {
+        let len = (len - MIN_MATCH) as u32;
+        if len < 8 {
+            rc.bit(&mut self.choice, 0);
+            rc.tree(&mut self.low[pos_state], 3, len)
+        } else if len < 16 {
+            rc.bit(&mut self.choice, 1);
+            rc.bit(&mut self.choice2, 0);
+            rc.tree(&mut self.mid[pos_state], 3, len - 8)
+        } else {
+            rc.bit(&mut self.choice, 1);
+            rc.bit(&mut self.choice2, 1);
+            rc.tree(&mut self.high, 8, len - 16)
+        }
+    }
+}
+
+/// Probabilities of the LZMA model and its state
+#[derive(Clone)]
+struct Model {
+    state: usize,
+    reps: [u32; 4],
+    is_match: [[u16; POS_STATES]; STATES],
+    is_rep: [u16; STATES],
+    is_rep_g0: [u16; STATES],
+    is_rep_g1: [u16; STATES],
+    is_rep_g2: [u16; STATES],
+    is_rep0_long: [[u16; POS_STATES]; STATES],
+    literal: Vec<[u16; 0x300]>,
+    pos_slot: [[u16; 64]; 4],
+    pos_special: [u16; 1 + FULL_DISTANCES - END_POS_MODEL],
+    align: [u16; 1 << ALIGN_BITS],
+    len: LenEncoder,
+    rep_len: LenEncoder,
+}
+
+impl Model {
+    fn new() -> Self {
+        Model {
+            state: 0,
+            reps: [0; 4],
+            is_match: [[PROB_INIT; POS_STATES]; STATES],
+            is_rep: [PROB_INIT; STATES],
+            is_rep_g0: [PROB_INIT; STATES],
+            is_rep_g1: [PROB_INIT; STATES],
+            is_rep_g2: [PROB_INIT; STATES],
+            is_rep0_long: [[PROB_INIT; POS_STATES]; STATES],
+            literal: vec![[PROB_INIT; 0x300]; 1 << LC],
+            pos_slot: [[PROB_INIT; 64]; 4],
+            pos_special: [PROB_INIT; 1 + FULL_DISTANCES - END_POS_MODEL],
+            align: [PROB_INIT; 1 << ALIGN_BITS],
+            len: LenEncoder::new(),
+            rep_len: LenEncoder::new(),
+        }
+    }
+
+    fn literal(&mut self, rc: &mut RangeEncoder, byte: u8, prev: u8, match_byte: u8, pos: u64) {
+        let pos_state = pos as usize & (POS_STATES - 1);
+        rc.bit(&mut self.is_match[self.state][pos_state], 0);
+        let probs = &mut self.literal[(prev >> (8 - LC)) as usize];
+        if self.state < 7 {
+            rc.tree(probs, 8, byte as u32)
+        } else {
+            let mut match_byte = match_byte as u32;
+            let mut symbol = byte as u32 | 0x100;
+            let mut offs = 0x100;
+            while symbol < 0x10000 {
+                match_byte <<= 1;
+                rc.bit(
+                    &mut probs[(offs + (match_byte & offs) + (symbol >> 8)) as usize],
+                    (symbol >> 7) & 1,
+                );
+                symbol <<= 1;
+                offs &= !(match_byte ^ symbol)
+            }
+        }
+        self.state = match self.state {
+            0..4 => 0,
+            4..10 => self.state - 3,
+            _ => self.state - 6,
+        }
+    }
+
+    /// a match of `dist` + 1 back
+    fn matched(&mut self, rc: &mut RangeEncoder, len: usize, dist: u32, pos: u64) {
+        let pos_state = pos as usize & (POS_STATES - 1);
+        rc.bit(&mut self.is_match[self.state][pos_state], 1);
+        rc.bit(&mut self.is_rep[self.state], 0);
+        self.len.encode(rc, len, pos_state);
+        let len_state = (len - MIN_MATCH).min(3);
+        let slot = pos_slot(dist);
+        rc.tree(&mut self.pos_slot[len_state], 6, slot);
+        if slot >= 4 {
+            let footer = (slot >> 1) - 1;
+            let base = (2 | (slot & 1)) << footer;
+            let reduced = dist - base;
+            if (slot as usize) < END_POS_MODEL {
+                let from = base as usize - slot as usize;
+                rc.reverse_tree(&mut self.pos_special[from..], footer, reduced)
+            } else {
+                rc.direct(reduced >> ALIGN_BITS, footer - ALIGN_BITS);
+                rc.reverse_tree(&mut self.align, ALIGN_BITS, reduced & 0xF)
+            }
+        }
+        self.reps = [dist, self.reps[0], self.reps[1], self.reps[2]];
+        self.state = if self.state < 7 { 7 } else { 10 }
+    }
+
+    /// a match of a recent distance, len 1 of rep 0 is a short rep
+    fn rep(&mut self, rc: &mut RangeEncoder, len: usize, rep: usize, pos: u64) {
+        let pos_state = pos as usize & (POS_STATES - 1);
+        rc.bit(&mut self.is_match[self.state][pos_state], 1);
+        rc.bit(&mut self.is_rep[self.state], 1);
+        if rep == 0 {
+            rc.bit(&mut self.is_rep_g0[self.state], 0);
+            rc.bit(
+                &mut self.is_rep0_long[self.state][pos_state],
+                (len > 1) as u32,
+            );
+            if len == 1 {
+                self.state = if self.state < 7 { 9 } else { 11 };
+                return;
+            }
+        } else {
+            rc.bit(&mut self.is_rep_g0[self.state], 1);
+            if rep == 1 {
+                rc.bit(&mut self.is_rep_g1[self.state], 0)
+            } else {
+                rc.bit(&mut self.is_rep_g1[self.state], 1);
+                rc.bit(&mut self.is_rep_g2[self.state], rep as u32 - 2)
+            }
+            let dist = self.reps[rep];
+            self.reps.copy_within(0..rep, 1);
+            self.reps[0] = dist
+        }
+        self.rep_len.encode(rc, len, pos_state);
+        self.state = if self.state < 7 { 8 } else { 11 }
+    }
+}
+
+fn pos_slot(dist: u32) -> u32 {
+    if dist < 4 {
+        dist
+    } else {
+        let bits = 31 - dist.leading_zeros();
+        (bits << 1) | ((dist >> (bits - 1)) & 1)
+    }
+}
+
+/// Match search parameters of a compression level
+struct Params {
+    dict_size: u32,
+    /// the maximum number of chain entries to check
+    chain: usize,
+    /// a match length stopping the search
+    nice: usize,
+    /// check the next position for a longer match
+    lazy: bool,
+}
+
+fn params(level: u8) -> Params {
+    let level = level.clamp(1, 9);
+    Params {
+        dict_size: 1 << (15 + level.min(8)),
+        chain: 4 << level,
+        nice: (16 << (level / 3)).min(MAX_MATCH),
+        lazy: level > 3,
+    }
+}
+
+/// LZMA encoder with a sliding dictionary, its output has no header
+pub(crate) struct LzmaCore {
+    params: Params,
+    /// the dictionary followed by not encoded yet data
+    buf: Vec<u8>,
+    /// an absolute position of `buf[0]`
+    base: u64,
+    /// an index in `buf` of not encoded yet data
+    start: usize,
+    /// last absolute positions of hashes
+    head: Vec<u32>,
+    /// previous absolute positions with the same hash
+    prev: Vec<u32>,
+    model: Model,
+    pub(crate) rc: RangeEncoder,
+}
+
+impl LzmaCore {
+    pub(crate) fn new(level: u8) -> Self {
+        let params = params(level);
+        let dict_size = params.dict_size as usize;
+        LzmaCore {
+            params,
+            buf: vec![],
+            base: 0,
+            start: 0,
+            head: vec![u32::MAX; 1 << HASH_BITS],
+            prev: vec![u32::MAX; dict_size],
+            model: Model::new(),
+            rc: RangeEncoder::new(),
+        }
+    }
+
+    pub(crate) fn dict_size(&self) -> u32 {
+        self.params.dict_size
+    }
+
+    /// a number of not encoded yet bytes
+    pub(crate) fn available(&self) -> usize {
+        self.buf.len() - self.start
+    }
+
+    /// data encoded last
+    pub(crate) fn encoded(&self, len: usize) -> &[u8] {
+        &self.buf[self.start - len..self.start]
+    }
+
+    /// resets probabilities and the state, LZMA2 does it after uncompressed chunks
+    pub(crate) fn reset_state(&mut self) {
+        self.model = Model::new()
+    }
+
+    /// adds data to encode, the dictionary gets slid when needed
+    pub(crate) fn append(&mut self, data: &[u8]) {
+        let dict_size = self.params.dict_size as usize;
+        if self.start > 2 * dict_size {
+            let shift = self.start - dict_size;
+            self.buf.drain(..shift);
+            self.base += shift as u64;
+            self.start -= shift
+        }
+        self.buf.extend_from_slice(data)
+    }
+
+    fn hash(&self, i: usize) -> usize {
+        let b = &self.buf[i..i + 3];
+        let h = (b[0] as u32) | (b[1] as u32) << 8 | (b[2] as u32) << 16;
+        (h.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, i: usize) {
+        if i + 3 <= self.buf.len() {
+            let h = self.hash(i);
+            let pos = (self.base + i as u64) as u32;
+            let slot = pos as usize % self.prev.len();
+            self.prev[slot] = self.head[h];
+            self.head[h] = pos
+        }
+    }
+
+    /// a length of data at `i` matching data `dist` + 1 back
+    fn match_len(&self, i: usize, dist: u32, max_len: usize) -> usize {
+        let j = i - dist as usize - 1;
+        self.buf[j..j + max_len]
+            .iter()
+            .zip(&self.buf[i..i + max_len])
+            .take_while(|(a, b)| a == b)
+            .count()
+    }
+
+    /// a distance back from `i` available for matches
+    fn history(&self, i: usize) -> usize {
+        ((self.base + i as u64) as usize).min(self.params.dict_size as usize)
+    }
+
+    /// finds the longest match of at least 3 bytes, returns its (len, dist)
+    fn find_match(&self, i: usize, max_len: usize) -> (usize, u32) {
+        let mut best = (0, 0);
+        if max_len < 3 {
+            return best;
+        }
+        let history = self.history(i) as u32;
+        let pos = (self.base + i as u64) as u32;
+        let mut candidate = self.head[self.hash(i)];
+        let mut last_dist = 0;
+        for _ in 0..self.params.chain {
+            if candidate == u32::MAX {
+                break;
+            }
+            let dist = pos.wrapping_sub(candidate);
+            if dist == 0 || dist > history || dist <= last_dist {
+                break;
+            }
+            last_dist = dist;
+            let best_len = best.0;
+            let j = i - dist as usize;
+            if self.buf[j + best_len] == self.buf[i + best_len] {
+                let len = self.match_len(i, dist - 1, max_len);
+                if len > best_len {
+                    best = (len, dist - 1);
+                    if len >= self.params.nice || len == max_len {
+                        break;
+                    }
+                }
+            }
+            candidate = self.prev[candidate as usize % self.prev.len()]
+        }
+        if best.0 < 3 || (best.0 == 3 && best.1 >= 0x4000) {
+            (0, 0)
+        } else {
+            best
+        }
+    }
+
+    /// the longest match of recent distances, returns its (len, rep)
+    fn find_rep(&self, i: usize, max_len: usize) -> (usize, usize) {
+        let history = self.history(i);
+        let mut best = (0, 0);
+        for (rep, &dist) in self.model.reps.iter().enumerate() {
+            if (dist as usize) < history && max_len >= MIN_MATCH {
+                let len = self.match_len(i, dist, max_len);
+                if len > best.0 {
+                    best = (len, rep)
+                }
+            }
+        }
+        best
+    }
+
+    /// encodes data till `end` relative to not encoded yet data, or till a compressed size exceeds
+    /// the limit, matches don't go over `end`, returns a number of encoded bytes
+    pub(crate) fn encode(&mut self, end: usize, limit: usize) -> usize {
+        let end = self.start + end;
+        let mut i = self.start;
+        let mut next: Option<(usize, u32)> = None;
+        while i < end && self.rc.pending() < limit {
+            let max_len = MAX_MATCH.min(end - i);
+            let pos = self.base + i as u64;
+            let (rep_len, rep) = self.find_rep(i, max_len);
+            let (len, dist) = next.take().unwrap_or_else(|| self.find_match(i, max_len));
+            self.insert(i);
+            let take_rep = rep_len >= MIN_MATCH
+                && (rep_len >= self.params.nice
+                    || rep_len + 1 >= len
+                    || (rep_len + 2 >= len && dist >= 1 << 9)
+                    || (rep_len + 3 >= len && dist >= 1 << 15));
+            let (len, step) = if take_rep {
+                self.model.rep(&mut self.rc, rep_len, rep, pos);
+                (rep_len, rep_len)
+            } else if len > 0 {
+                if self.params.lazy && len < self.params.nice && i + 1 < end {
+                    let following = self.find_match(i + 1, MAX_MATCH.min(end - i - 1));
+                    if following.0 > len + 1 || (following.0 == len + 1 && following.1 < dist) {
+                        next = Some(following);
+                        self.literal(i, pos);
+                        i += 1;
+                        continue;
+                    }
+                }
+                self.model.matched(&mut self.rc, len, dist, pos);
+                (len, len)
+            } else {
+                let rep0 = self.model.reps[0] as usize;
+                if rep0 < self.history(i) && self.buf[i] == self.buf[i - rep0 - 1] {
+                    self.model.rep(&mut self.rc, 1, 0, pos)
+                } else {
+                    self.literal(i, pos)
+                }
+                (1, 1)
+            };
+            for j in i + 1..i + len {
+                self.insert(j)
+            }
+            i += step
+        }
+        let encoded = i - self.start;
+        self.start = i;
+        encoded
+    }
+
+    fn literal(&mut self, i: usize, pos: u64) {
+        let prev = if pos > 0 { self.buf[i - 1] } else { 0 };
+        let rep0 = self.model.reps[0] as usize;
+        let match_byte = if rep0 < self.history(i) {
+            self.buf[i - rep0 - 1]
+        } else {
+            0
+        };
+        self.model
+            .literal(&mut self.rc, self.buf[i], prev, match_byte, pos)
+    }
+
+    /// writes the end of stream marker
+    pub(crate) fn end_marker(&mut self) {
+        let pos = self.base + self.start as u64;
+        self.model
+            .matched(&mut self.rc, MIN_MATCH, 0xFFFF_FFFF, pos)
+    }
+}
+
+/// Compresses a content coming by chunks in the zip LZMA format,
+/// the end is marked by the end of stream marker
+pub(crate) struct Lzma {
+    core: LzmaCore,
+    started: bool,
+}
+
+impl Lzma {
+    pub(crate) fn new(level: u8) -> Self {
+        Lzma {
+            core: LzmaCore::new(level),
+            started: false,
+        }
+    }
+
+    /// writes completed bytes
+    fn flush(&mut self, out: &mut dyn Write) -> io::Result<u64> {
+        let mut res = 0;
+        if !self.started {
+            out.write_all(&SDK_VERSION)?;
+            out.write_all(&5_u16.to_le_bytes())?;
+            out.write_all(&[PROPS])?;
+            out.write_all(&self.core.dict_size().to_le_bytes())?;
+            res += 9;
+            self.started = true
+        }
+        out.write_all(&self.core.rc.out)?;
+        res += self.core.rc.out.len() as u64;
+        self.core.rc.out.clear();
+        Ok(res)
+    }
+}
+
+impl Encoder for Lzma {
+    fn encode(&mut self, chunk: &[u8], out: &mut dyn Write) -> io::Result<u64> {
+        self.core.append(chunk);
+        // a match may go over the chunk
+        if self.core.available() > MAX_MATCH {
+            let end = self.core.available() - MAX_MATCH;
+            self.core.encode(end, usize::MAX);
+        }
+        self.flush(out)
+    }
+
+    fn finish(&mut self, out: &mut dyn Write) -> io::Result<u64> {
+        let end = self.core.available();
+        self.core.encode(end, usize::MAX);
+        self.core.end_marker();
+        self.core.rc.flush();
+        self.flush(out)
+    }
+}
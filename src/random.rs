@@ -0,0 +1,48 @@
+//! Random bytes of the platform cryptographically secure generator, used for salts and
+//! encryption headers
+use std::io::{self, Error};
+#[cfg(unix)]
+use std::{fs::File, io::Read};
+
+/// fills the buffer by random bytes of /dev/urandom
+#[cfg(unix)]
+pub(crate) fn fill(buf: &mut [u8]) -> io::Result<()> {
+    File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(buf))
+        .map_err(|e| Error::new(e.kind(), format! {"can't read /dev/urandom: {e}"}))
+}
+
+#[cfg(target_os = "windows")]
+#[link(name = "bcrypt")]
+unsafe extern "system" {
+    fn BCryptGenRandom(algorithm: *mut u8, buf: *mut u8, len: u32, flags: u32) -> i32;
+}
+
+/// fills the buffer by random bytes of the system preferred generator of BCryptGenRandom
+#[cfg(target_os = "windows")]
+pub(crate) fn fill(buf: &mut [u8]) -> io::Result<()> {
+    const BCRYPT_USE_SYSTEM_PREFERRED_RNG: u32 = 2;
+    // SAFETY: the buffer is valid for its length, no algorithm handle is needed with the flag
+    let status = unsafe {
+        BCryptGenRandom(
+            std::ptr::null_mut(),
+            buf.as_mut_ptr(),
+            buf.len() as u32,
+            BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+        )
+    };
+    match status {
+        0 => Ok(()),
+        _ => Err(Error::other(
+            format! {"BCryptGenRandom failed with {status:#x}"},
+        )),
+    }
+}
+
+#[cfg(not(any(unix, target_os = "windows")))]
+pub(crate) fn fill(_buf: &mut [u8]) -> io::Result<()> {
+    Err(Error::new(
+        io::ErrorKind::Unsupported,
+        "no secure random generator is known on the platform",
+    ))
+}
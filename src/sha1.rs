@@ -0,0 +1,145 @@
+//! SHA-1 and HMAC-SHA1 used by AES encryption
+pub(crate) const DIGEST_LEN: usize = 20;
+const BLOCK_LEN: usize = 64;
+
+/// Calculates SHA-1 of data coming by chunks
+#[derive(Clone)]
+pub(crate) struct Sha1 {
+    state: [u32; 5],
+    block: [u8; BLOCK_LEN],
+    /// a number of bytes in the block
+    filled: usize,
+    len: u64,
+}
+
+impl Sha1 {
+    pub(crate) fn new() -> Self {
+        Sha1 {
+            state: [
+                0x6745_2301,
+                0xEFCD_AB89,
+                0x98BA_DCFE,
+                0x1032_5476,
+                0xC3D2_E1F0,
+            ],
+            block: [0; BLOCK_LEN],
+            filled: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let n = (BLOCK_LEN - self.filled).min(data.len());
+            self.block[self.filled..self.filled + n].copy_from_slice(&data[..n]);
+            self.filled += n;
+            data = &data[n..];
+            if self.filled == BLOCK_LEN {
+                self.compress();
+                self.filled = 0
+            }
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> [u8; DIGEST_LEN] {
+        let bits = self.len * 8;
+        self.update(&[0x80]);
+        while self.filled != BLOCK_LEN - 8 {
+            self.update(&[0])
+        }
+        self.update(&bits.to_be_bytes());
+        let mut res = [0_u8; DIGEST_LEN];
+        for (i, word) in self.state.iter().enumerate() {
+            res[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes())
+        }
+        res
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0_u32; 80];
+        for (i, word) in self.block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap())
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1)
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..20 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..40 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..60 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6_u32),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value)
+        }
+    }
+}
+
+/// Calculates HMAC-SHA1, the keyed state can be cloned for a reuse
+#[derive(Clone)]
+pub(crate) struct Hmac {
+    inner: Sha1,
+    outer: Sha1,
+}
+
+impl Hmac {
+    pub(crate) fn new(key: &[u8]) -> Self {
+        let mut block = [0_u8; BLOCK_LEN];
+        if key.len() > BLOCK_LEN {
+            let mut sha = Sha1::new();
+            sha.update(key);
+            block[..DIGEST_LEN].copy_from_slice(&sha.finish())
+        } else {
+            block[..key.len()].copy_from_slice(key)
+        }
+        let mut inner = Sha1::new();
+        inner.update(&block.map(|b| b ^ 0x36));
+        let mut outer = Sha1::new();
+        outer.update(&block.map(|b| b ^ 0x5C));
+        Hmac { inner, outer }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.inner.update(data)
+    }
+
+    pub(crate) fn finish(self) -> [u8; DIGEST_LEN] {
+        let mut outer = self.outer;
+        outer.update(&self.inner.finish());
+        outer.finish()
+    }
+}
+
+/// derives a key of the out length from a password by PBKDF2 with HMAC-SHA1
+pub(crate) fn pbkdf2(password: &[u8], salt: &[u8], iterations: u32, out: &mut [u8]) {
+    let keyed = Hmac::new(password);
+    for (i, chunk) in out.chunks_mut(DIGEST_LEN).enumerate() {
+        let mut hmac = keyed.clone();
+        hmac.update(salt);
+        hmac.update(&(i as u32 + 1).to_be_bytes());
+        let mut u = hmac.finish();
+        let mut t = u;
+        for _ in 1..iterations {
+            let mut hmac = keyed.clone();
+            hmac.update(&u);
+            u = hmac.finish();
+            t.iter_mut().zip(u).for_each(|(t, u)| *t ^= u)
+        }
+        chunk.copy_from_slice(&t[..chunk.len()])
+    }
+}
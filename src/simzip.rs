@@ -3,20 +3,24 @@
 #[cfg(feature = "deflate")]
 extern crate libdeflater;
 extern crate simtime;
+use crate::aes::{self, Aes};
+use crate::codec::{self, Encoder};
 use crate::crc32;
+use crate::glob;
+use crate::ignore::{self, Ignore};
 use crate::simzip::Location::Disk;
 use crate::simzip::Location::Mem;
-#[cfg(feature = "deflate")]
-use libdeflater::{CompressionLvl, Compressor};
+use crate::tz;
+use crate::zipcrypto::{self, ZipCrypto};
 #[cfg(unix)]
-use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::fs::MetadataExt;
 use std::{
     cell::Cell,
-    collections::HashSet,
-    fs::{self, File},
-    hash::{Hash, Hasher},
-    io::{self, Error, Read, Seek, Write},
-    path::{Path, PathBuf},
+    collections::{HashSet, hash_map::RandomState},
+    fs::{self, File, OpenOptions},
+    hash::{BuildHasher, Hash, Hasher},
+    io::{self, BufWriter, Cursor, Error, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
 };
 
@@ -25,7 +29,7 @@ use std::{
 #[derive(Debug, Clone, PartialEq, Default)]
 pub enum Compression {
     /// only store
-    #[cfg_attr(not(feature = "deflate"), default)]
+    #[cfg_attr(not(any(feature = "deflate", feature = "rust_deflate")), default)]
     Store,
     /// shrink
     Shrink,
@@ -39,8 +43,10 @@ pub enum Compression {
     Reduction4,
     /// implode
     Implode,
-    /// deflate
-    #[cfg_attr(feature = "deflate", default)]
+    /// deflate, with the `deflate` feature libdeflate compresses entries in memory and the
+    /// built-in streaming encoder compresses external files, with `rust_deflate` the built-in
+    /// encoder compresses all entries
+    #[cfg_attr(any(feature = "deflate", feature = "rust_deflate"), default)]
     Deflate,
     /// deflate 64
     Deflat64,
@@ -48,10 +54,28 @@ pub enum Compression {
     BZIP2,
     /// lzma
     LZMA,
+    /// zstandard
+    Zstd,
+    /// xz
+    Xz,
     /// ppmd
     PPMd,
 }
 
+/// Password encryption
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Encryption {
+    /// traditional PKWARE, weak
+    #[default]
+    ZipCrypto,
+    /// WinZip AES-128
+    Aes128,
+    /// WinZip AES-192
+    Aes192,
+    /// WinZip AES-256
+    Aes256,
+}
+
 #[derive(Debug)]
 pub enum Location {
     Disk(PathBuf),
@@ -90,22 +114,47 @@ pub struct ZipEntry {
     pub comment: Option<String>,
     /// attributes
     pub attributes: HashSet<Attribute>,
-    /// compression algorithm
+    /// Unix mode as st_mode, taken from an external content when not set, attributes apply over it
+    pub mode: Option<u32>,
+    /// compression algorithm, the zip store policy applies to the default one unless
+    /// it's set by `with_compression`
     pub compression: Compression,
+    /// compression level 0-12, 0-22 for zstd, the zip level is used when not set
+    pub level: Option<u8>,
+    /// encryption password, the zip password is used when not set
+    pub password: Option<String>,
+    /// encryption used with a password, the zip encryption is used when not set
+    pub encryption: Option<Encryption>,
     data: Location, // includes len uncompressed (original)
-    len: u32,       // compressed TODO change to usize or a custom type
+    /// the compression is set by `with_compression`
+    explicit_compression: bool,
+    len: u64,       // compressed
+    size: u64,      // uncompressed (original)
     crc: Cell<u32>, // crc32
-    offset: u32,    // the header offset in a zip
-    modified: u64,  // in secs since epoch
-    #[cfg(any(unix, target_os = "redox"))]
-    uid: u32,
-    #[cfg(any(unix, target_os = "redox"))]
-    gid: u32,
-    #[cfg(any(unix, target_os = "redox"))]
-    created: u64,
-    #[cfg(any(unix, target_os = "redox"))]
-    times_mask: Cell<u8>, // times field mask
-                          // #[cfg(target_os = "windows")]
+    offset: u64,    // the header offset in a zip
+    zip64: bool,    // the local header has zip64 extra
+    flags: u16,     // general purpose flags
+    /// compression used, Store when the compression doesn't pay
+    method: Compression,
+    /// AES strength 1-3, 0 when AES isn't used
+    aes_strength: u8,
+    /// a directory entry
+    dir: bool,
+    /// a symbolic link entry keeping the link target as content
+    link: bool,
+    /// Unix mode of an external content or a default one
+    st_mode: u32,
+    /// uid and gid written in "ux" extra
+    owner: Option<(u32, u32)>,
+    /// explicit modification, access and creation times
+    times: [Option<SystemTime>; 3],
+    /// modification, access and creation times resolved when storing,
+    /// the modification time is always set
+    file_times: [Option<SystemTime>; 3],
+    /// modification, access and creation times written in NTFS extra
+    ntfs: Option<[u64; 3]>,
+    /// DOS time and date of the modification time
+    dos_datetime: (u16, u16),
 }
 
 /// Keeps information about zip file
@@ -116,10 +165,169 @@ pub struct ZipInfo {
     directory: Option<HashSet<DirEntry>>,
     /// Zip file comment
     pub comment: Option<String>,
+    /// compression level 0-12, 0-22 for zstd, of entries not setting own level, default 6 when not set,
+    /// a level over a maximum of a method is lowered to it
+    pub level: Option<u8>,
+    /// stores already compressed entries with the default compression not set by `with_compression`
+    pub store_policy: StorePolicy,
+    /// encryption password of entries not setting own password
+    pub password: Option<String>,
+    /// encryption of entries not setting own encryption, ZipCrypto when not set
+    pub encryption: Option<Encryption>,
+    /// uid and gid of entries
+    pub ownership: Ownership,
+    /// writes NTFS extra with times of 100ns precision
+    pub ntfs_times: bool,
+    /// a time zone of DOS times of entries, UT and NTFS extras are always in UTC
+    pub dos_time: DosTime,
     entries: Vec<ZipEntry>,
+    warnings: Vec<String>,
+}
+
+/// A time zone of DOS times which have no time zone information
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DosTime {
+    /// the local time with the daylight saving time in effect at each time, as unzip expects
+    #[default]
+    Local,
+    /// UTC, so the zip content doesn't depend on a time zone of the zipping machine
+    Utc,
+}
+
+/// Ownership of entries written in Info-ZIP Unix extra
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Ownership {
+    /// uid and gid of external files, vector entries have no ownership
+    #[default]
+    Keep,
+    /// no ownership
+    Omit,
+    /// the uid and gid for all entries
+    Map(u32, u32),
+}
+
+/// Selects files and directories added by `ZipInfo::add_dir`
+#[derive(Debug, Clone, Default)]
+pub struct DirOptions {
+    /// glob patterns of relative file paths to add, all files are added when empty
+    pub include: Vec<String>,
+    /// glob patterns of relative file and directory paths to skip
+    pub exclude: Vec<String>,
+    /// add files and directories which names start by a dot
+    pub hidden: bool,
+    /// a zip path of the directory content, the content goes at the top when not set
+    pub prefix: Option<String>,
+    /// names of ignore files in the .gitignore format, as `.gitignore` or `.ignore`,
+    /// their rules apply to paths below their directories
+    pub ignore_files: Vec<String>,
+    /// how symbolic links are added
+    pub symlinks: Symlinks,
+}
+
+/// Adding of symbolic links of a directory tree
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Symlinks {
+    /// adds content of files links point to, links to directories are skipped
+    #[default]
+    Follow,
+    /// stores links as links, a link pointing outside the tree fails adding
+    Store,
+    /// stores links as links, content of files outside the tree is added for links pointing there,
+    /// other links pointing outside fail adding
+    StoreInside,
+}
+
+/// Detects already compressed content which is stored without compression
+#[derive(Debug, Clone)]
+pub struct StorePolicy {
+    /// lower case extensions of entry names
+    pub extensions: Vec<String>,
+    /// checks leading magic bytes of a content
+    pub sniff: bool,
+}
+
+/// leading bytes of compressed formats, the offset and the magic
+static MAGICS: &[(usize, &[u8])] = &[
+    (0, b"\x89PNG\r\n\x1A\n"),
+    (0, b"\xFF\xD8\xFF"), // jpeg
+    (0, b"GIF8"),
+    (8, b"WEBP"),
+    (0, b"PK\x03\x04"), // zip, jar, docx
+    (0, b"\x1F\x8B"),   // gzip
+    (0, b"BZh"),
+    (0, b"\xFD7zXZ\x00"),
+    (0, b"\x28\xB5\x2F\xFD"), // zstd
+    (0, b"7z\xBC\xAF\x27\x1C"),
+    (0, b"Rar!\x1A\x07"),
+    (4, b"ftyp"), // mp4, mov
+    (0, b"ID3"),  // mp3
+    (0, b"OggS"),
+];
+
+impl Default for StorePolicy {
+    fn default() -> Self {
+        StorePolicy {
+            extensions: [
+                "png", "jpg", "jpeg", "gif", "webp", "zip", "jar", "gz", "tgz", "bz2", "xz", "zst",
+                "7z", "rar", "mp3", "mp4", "mov", "ogg",
+            ]
+            .map(String::from)
+            .to_vec(),
+            sniff: true,
+        }
+    }
+}
+
+impl StorePolicy {
+    /// tells if the entry content is already compressed
+    fn applies(&self, entry: &ZipEntry) -> bool {
+        let extension = Path::new(&entry.name)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase());
+        if let Some(extension) = extension
+            && self.extensions.contains(&extension)
+        {
+            return true;
+        }
+        if !self.sniff {
+            return false;
+        }
+        let mut head = [0_u8; 16];
+        let len = match &entry.data {
+            Location::Mem(mem) => {
+                let len = mem.len().min(head.len());
+                head[..len].copy_from_slice(&mem[..len]);
+                len
+            }
+            // the content errors get reported at storing
+            Location::Disk(path) => File::open(path)
+                .and_then(|f| f.take(head.len() as u64).read(&mut head))
+                .unwrap_or(0),
+        };
+        MAGICS
+            .iter()
+            .any(|(offset, magic)| head[..len].get(*offset..offset + magic.len()) == Some(magic))
+    }
 }
 
 static VER_EXTRACT: u16 = 0x14;
+static VER_EXTRACT_ZIP64: u16 = 0x2D;
+static VER_EXTRACT_BZIP2: u16 = 0x2E;
+static VER_EXTRACT_AES: u16 = 0x33;
+static VER_EXTRACT_LZMA: u16 = 0x3F;
+/// the compression method signaling that the actual one is in AES extra
+static METHOD_AES: u16 = 99;
+/// AES extra is of AE-2 version which doesn't use CRC
+static AES_EXTRA: [u8; 8] = [0x01, 0x99, 7, 0, 2, 0, b'A', b'E'];
+/// Unix file types
+static S_IFMT: u32 = 0o170000;
+static S_IFREG: u32 = 0o100000;
+static S_IFDIR: u32 = 0o040000;
+static S_IFLNK: u32 = 0o120000;
+/// a value of 32 bits fields signaling that the actual value is in zip64 extra
+static ZIP64_LIMIT: u64 = 0xFFFF_FFFF;
+/// a size of chunks an external content is read by
+static CHUNK_SIZE: usize = 64 * 1024;
 
 impl Compression {
     fn value(&self) -> u16 {
@@ -135,221 +343,528 @@ impl Compression {
             Compression::Deflat64 => 9,
             Compression::BZIP2 => 12,
             Compression::LZMA => 14,
+            Compression::Zstd => 93,
+            Compression::Xz => 95,
             Compression::PPMd => 98,
         }
     }
+
+    /// version needed to extract
+    fn version(&self) -> u16 {
+        match *self {
+            Compression::BZIP2 => VER_EXTRACT_BZIP2, // version 4.6
+            Compression::LZMA | Compression::Zstd | Compression::Xz => VER_EXTRACT_LZMA, // version 6.3
+            _ => VER_EXTRACT, // version 2.0
+        }
+    }
+}
+
+impl Encryption {
+    /// AES strength, 0 for ZipCrypto
+    fn strength(&self) -> u8 {
+        match *self {
+            Encryption::ZipCrypto => 0,
+            Encryption::Aes128 => 1,
+            Encryption::Aes192 => 2,
+            Encryption::Aes256 => 3,
+        }
+    }
+}
+
+/// Encrypts entries content
+enum Cipher<'a> {
+    /// a password and a check byte
+    ZipCrypto(&'a str, u8),
+    /// a password and a strength
+    Aes(&'a str, u8),
+}
+
+impl Cipher<'_> {
+    /// a length added to the content
+    fn overhead(&self) -> u64 {
+        match *self {
+            Cipher::ZipCrypto(..) => zipcrypto::HEADER_LEN,
+            Cipher::Aes(_, strength) => aes::overhead(strength),
+        }
+    }
 }
 
 // info: https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
 // https://users.cs.jmu.edu/buchhofp/forensics/formats/pkzip-printable.html
 impl ZipEntry {
-    /// stores zip file on disk
-    fn store(&mut self, mut zip_file: &File) -> io::Result<usize> {
+    /// stores zip entry in the zip
+    fn store<W: Write + Seek>(
+        &mut self,
+        zip_file: &mut W,
+        options: StoreOptions,
+        warnings: &mut Vec<String>,
+    ) -> io::Result<usize> {
+        let StoreOptions {
+            descriptor,
+            compression,
+            level,
+            password,
+            encryption,
+            ownership,
+            ntfs_times,
+            dos_time,
+        } = options;
+        if self.name.is_empty() && self.path.is_none() {
+            return Err(Error::other("an entry has no name and no zip path"));
+        }
         let mut res = 0_usize;
-        // TODO impl zip64
-        self.offset = zip_file.stream_position()? as u32;
+        self.offset = zip_file.stream_position()?;
+        self.method = compression;
+        self.aes_strength = match password {
+            Some(_) => encryption.strength(),
+            None => 0,
+        };
+        self.size = match &self.data {
+            _ if self.dir => 0,
+            Location::Mem(mem) => mem.len() as _,
+            Location::Disk(path) if self.link => link_target(path)?.len() as _,
+            Location::Disk(path) => fs::metadata(path)?.len(),
+        };
+        // a compressed size isn't known yet, so leave a room for a compression overhead
+        self.zip64 = self.size + (self.size >> 10) + 0x400 >= ZIP64_LIMIT;
+        let zip64 = self.zip64;
+        let aes = self.aes_strength > 0;
+        let ver_extract = |method: &Compression| {
+            if zip64 {
+                VER_EXTRACT_ZIP64 // version 4.5
+            } else {
+                VER_EXTRACT // version 2.0
+            }
+            .max(method.version())
+            .max(if aes { VER_EXTRACT_AES } else { 0 }) // version 5.1
+        };
         zip_file.write_all(&(0x504b0304_u32.to_be_bytes()))?;
         res += 4;
-        zip_file.write_all(&VER_EXTRACT.to_ne_bytes())?; // version 2.0
+        zip_file.write_all(&ver_extract(&self.method).to_ne_bytes())?;
         res += 2;
         // flags
-        // set to 0x08 and then add a data descriptor after data 3x4 bytes
         let mut flags = 0_u16;
+        if let Compression::Deflate | Compression::Deflat64 = self.method {
+            // as Info-ZIP does
+            flags |= match level {
+                0..=1 => 0b110, // super fast
+                2 => 0b100,     // fast
+                3..=7 => 0,     // normal
+                _ => 0b010,     // maximum
+            }
+        }
+        if let Compression::LZMA = self.method {
+            flags |= 0b010; // end of stream marker
+        }
+        if password.is_some() {
+            flags |= 1; // encrypted
+        }
+        if descriptor {
+            flags |= 1 << 3; // data descriptor
+        }
         flags |= 1 << 11; // utf8
+        self.flags = flags;
         zip_file.write_all(&flags.to_ne_bytes())?; // flags
         res += 2;
-        zip_file.write_all(&self.compression.value().to_ne_bytes())?;
+        zip_file.write_all(&self.method_value().to_ne_bytes())?;
         res += 2;
-        let (comm_len, crc_pos) = self.write_common(zip_file)?;
+        let crc_sizes = if descriptor {
+            (0, 0, 0)
+        } else if self.zip64 {
+            (0, ZIP64_LIMIT as u32, ZIP64_LIMIT as u32)
+        } else {
+            (0, 0, self.size as u32)
+        };
+        self.file_times = self.resolve_times()?;
+        let (datetime, clamped) = dos_datetime(self.mtime(), dos_time);
+        self.dos_datetime = datetime;
+        let name = self.combined_name();
+        if clamped {
+            warnings.push(format! {"the time of {name} is out of the DOS range 1980-2107, clamped"})
+        }
+        #[cfg(any(unix, target_os = "redox"))]
+        if (self.file_times.iter().flatten()).any(|&time| ut_secs(time).1) {
+            warnings.push(format! {"the time of {name} is out of the UT range 1901-2038, clamped"})
+        }
+        let (comm_len, crc_pos, time) = self.write_common(zip_file, crc_sizes)?;
         res += comm_len;
-        let combined_name = match &self.path {
-            Some(path) => path.to_owned() + "/" + &self.name,
-            None => self.name.clone(),
+        self.owner = match (ownership, &self.data) {
+            (Ownership::Map(uid, gid), _) => Some((uid, gid)),
+            #[cfg(unix)]
+            (Ownership::Keep, Location::Disk(path)) => {
+                let metadata = self.metadata(path)?;
+                Some((metadata.uid(), metadata.gid()))
+            }
+            _ => None,
+        };
+        let ux = self.owner.map(ux_extra).unwrap_or_default();
+        self.ntfs = if ntfs_times {
+            let mtime = self.mtime();
+            Some(self.file_times.map(|time| filetime(time.unwrap_or(mtime))))
+        } else {
+            None
         };
+        let ntfs = self.ntfs.map(ntfs_extra).unwrap_or_default();
+        let combined_name = self.combined_name();
         let name_bytes = combined_name.as_bytes();
         zip_file.write_all(&(name_bytes.len() as u16).to_ne_bytes())?;
         res += 2;
         #[cfg(any(unix, target_os = "redox"))]
-        let mut time_headers = 0;
-        #[cfg(any(unix, target_os = "redox"))]
-        let mut mask = 0_u8;
-        #[cfg(any(unix, target_os = "redox"))]
-        // TODO improve by reading metadata only once
-        let (atime, ctime, mtime) = match &self.data {
-            Location::Mem(_) => {
-                self.created = SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs();
-                (0, self.created, 0)
-            }
-            Location::Disk(path) => {
-                let metadata = fs::metadata(path)?;
-                (
-                    metadata.atime() as _,
-                    metadata.ctime() as _,
-                    metadata.mtime() as _,
-                )
-            }
-        };
+        let time_headers = self.file_times.iter().flatten().count();
         #[cfg(any(unix, target_os = "redox"))]
-        {
-            if mtime > 0 {
-                time_headers += 1;
-                mask |= 0b0000_0001
-            }
-            if atime > 0 {
-                time_headers += 1;
-                mask |= 0b0000_0010
-            }
-            if ctime > 0 {
-                time_headers += 1;
-                mask |= 0b0000_0100
-            }
-        }
-        #[cfg(any(unix, target_os = "redox"))]
-        self.times_mask.set(mask);
-        #[cfg(any(unix, target_os = "redox"))]
-        let extra_len = (2 + 2 + 1 + time_headers * 4) as u16;
+        let mut extra_len = (2 + 2 + 1 + time_headers * 4) as u16;
         #[cfg(target_os = "windows")]
-        let extra_len = 0_u16;
+        let mut extra_len = 0_u16;
+        if self.zip64 {
+            extra_len += 2 + 2 + 8 + 8
+        }
+        if aes {
+            extra_len += AES_EXTRA.len() as u16 + 1 + 2
+        }
+        extra_len += (ux.len() + ntfs.len()) as u16;
         zip_file.write_all(&extra_len.to_ne_bytes())?; // extra fields
         res += 2;
         zip_file.write_all(name_bytes)?;
         res += name_bytes.len();
         // write extra headers here
+        // zip64 local header extra has to include both sizes
+        let mut zip64_pos = 0;
+        if self.zip64 {
+            zip_file.write_all(&0x0001_u16.to_le_bytes())?; // zip64
+            res += 2;
+            zip_file.write_all(&16_u16.to_le_bytes())?; // len
+            res += 2;
+            let size = if descriptor { 0 } else { self.size };
+            zip_file.write_all(&size.to_le_bytes())?; // original
+            res += 8;
+            zip64_pos = zip_file.stream_position()?;
+            zip_file.write_all(&0_u64.to_le_bytes())?; // compressed
+            res += 8;
+        }
+        let mut aes_pos = 0;
+        if aes {
+            zip_file.write_all(&AES_EXTRA)?;
+            res += AES_EXTRA.len();
+            zip_file.write_all(&[self.aes_strength])?;
+            res += 1;
+            aes_pos = zip_file.stream_position()?;
+            zip_file.write_all(&self.method.value().to_le_bytes())?; // actual method
+            res += 2;
+        }
+        // ("ux")
+        zip_file.write_all(&ux)?;
+        res += ux.len();
+        zip_file.write_all(&ntfs)?;
+        res += ntfs.len();
         #[cfg(any(unix, target_os = "redox"))]
         {
             zip_file.write_all(&(0x5455_u16.to_ne_bytes()))?; // OS
             res += 2;
             zip_file.write_all(&(((1 + time_headers * 4) as u16).to_ne_bytes()))?; // OS
             res += 2;
-            zip_file.write_all(&(mask.to_ne_bytes()))?; // OS
+            zip_file.write_all(&(self.times_mask().to_ne_bytes()))?; // OS
             res += 1;
-            if mtime > 0 {
-                zip_file.write_all(&(mtime as u32).to_ne_bytes())?; // OS
-                res += 4;
-            }
-            if atime > 0 {
-                zip_file.write_all(&(atime as u32).to_ne_bytes())?; // OS
-                res += 4;
-            }
-            if ctime > 0 {
-                zip_file.write_all(&(ctime as u32).to_ne_bytes())?; // OS
+            for time in self.file_times.iter().flatten() {
+                zip_file.write_all(&ut_secs(*time).0.to_ne_bytes())?; // OS
                 res += 4;
             }
         }
 
         // writing content
-        match &self.data {
-            Location::Mem(mem) => {
-                match self.compression {
-                    Compression::Store => {
-                        zip_file.write_all(mem)?;
-                        self.len = mem.len() as u32;
-                        self.crc = crc32::update_fast_16(0 /*u32::MAX*/, mem).into()
-                    }
-                    #[cfg(feature = "deflate")]
-                    Compression::Deflate => {
-                        let mut compressor = Compressor::new(CompressionLvl::default());
-                        let max_sz = compressor.deflate_compress_bound(mem.len());
-                        let mut compressed_data = Vec::new();
-                        compressed_data.resize(max_sz, 0);
-                        let actual_sz = compressor
-                            .deflate_compress(&mem, &mut compressed_data)
-                            .map_err(|e| Error::other(format!("because {e}")))?;
-                        compressed_data.resize(actual_sz, 0);
-                        zip_file.write_all(&compressed_data)?;
-                        self.len = compressed_data.len() as u32;
-                        self.crc = crc32::update_slow(0 /*u32::MAX*/, &mem).into()
-                    }
-                    _ => {
-                        return Err(Error::other(
-                            format! {"compression {:?} isn't supported yet", self.compression},
-                        ))
-                    }
-                }
-                res += self.len as usize;
+        let data_pos = zip_file.stream_position()?;
+        // the last byte of the traditional encryption header lets check a password
+        let cipher = match password.as_deref() {
+            Some(password) if aes => Some(Cipher::Aes(password, self.aes_strength)),
+            Some(password) if descriptor => Some(Cipher::ZipCrypto(password, (time >> 8) as u8)),
+            Some(password) => {
+                let mut store = codec::encoder(&Compression::Store, level)?;
+                let (_, crc) = self.write_content(store.as_mut(), &mut io::sink())?;
+                Some(Cipher::ZipCrypto(password, (crc >> 24) as u8))
             }
-            Location::Disk(path) => {
-                // TODO consider a streaming way
-                let mut f = File::open(&**path)?;
-                let mut mem = vec![];
-                f.read_to_end(&mut mem)?;
-                match self.compression {
-                    Compression::Store => {
-                        zip_file.write_all(&mem)?;
-                        self.len = mem.len() as u32;
-                        self.crc = crc32::update_slow(0 /*u32::MAX*/, &mem).into()
-                    }
-                    #[cfg(feature = "deflate")]
-                    Compression::Deflate => {
-                        let mut compressor = Compressor::new(CompressionLvl::default());
-                        let max_sz = compressor.deflate_compress_bound(mem.len());
-                        let mut compressed_data = Vec::new();
-                        compressed_data.resize(max_sz, 0);
-                        let actual_sz = compressor
-                            .deflate_compress(&mem, &mut compressed_data)
-                            .map_err(|e| Error::other(format!("because {e}")))?;
-                        compressed_data.resize(actual_sz, 0);
-                        zip_file.write_all(&compressed_data)?;
-                        self.len = compressed_data.len() as u32;
-                        self.crc = crc32::update_slow(0 /*u32::MAX*/, &mem).into()
-                    }
-                    _ => {
-                        return Err(Error::other(
-                            format! {"compression {:?} isn't supported yet", self.compression},
-                        ))
-                    }
+            None => None,
+        };
+        let overhead = cipher.as_ref().map_or(0, Cipher::overhead);
+        let mut encoder = codec::encoder(&self.method, level)?;
+        let content = if descriptor || self.method == Compression::Store {
+            Some(self.write_encrypted(encoder.as_mut(), zip_file, cipher.as_ref())?)
+        } else {
+            // the compressed content can't get over the original size, so the stored one
+            // can replace it when the compression doesn't pay
+            let mut limited = Limited {
+                inner: zip_file,
+                left: self.size + overhead,
+                exceeded: false,
+            };
+            match self.write_encrypted(encoder.as_mut(), &mut limited, cipher.as_ref()) {
+                Ok((len, _)) if len >= self.size + overhead => None,
+                Err(_) if limited.exceeded => None,
+                content => Some(content?),
+            }
+        };
+        let (len, crc) = match content {
+            Some(content) => content,
+            None => {
+                self.method = Compression::Store;
+                self.flags &= !0b110;
+                zip_file.seek(std::io::SeekFrom::Start(self.offset + 4))?;
+                zip_file.write_all(&ver_extract(&self.method).to_ne_bytes())?;
+                zip_file.write_all(&self.flags.to_ne_bytes())?;
+                zip_file.write_all(&self.method_value().to_ne_bytes())?;
+                if aes {
+                    zip_file.seek(std::io::SeekFrom::Start(aes_pos))?;
+                    zip_file.write_all(&self.method.value().to_le_bytes())?;
                 }
-                res += self.len as usize;
+                zip_file.seek(std::io::SeekFrom::Start(data_pos))?;
+                let mut encoder = codec::encoder(&self.method, level)?;
+                self.write_encrypted(encoder.as_mut(), zip_file, cipher.as_ref())?
+            }
+        };
+        self.len = len;
+        // AE-2 hides CRC which could reveal the content
+        let crc = if aes { 0 } else { crc };
+        self.crc.set(crc);
+        res += self.len as usize;
+        if !self.zip64 && self.len >= ZIP64_LIMIT {
+            return Err(Error::other(
+                format! {"compressed {} exceeds 4GB", self.name},
+            ));
+        }
+        if descriptor {
+            zip_file.write_all(&(0x08074b50_u32.to_le_bytes()))?;
+            res += 4;
+            zip_file.write_all(&crc.to_le_bytes())?;
+            res += 4;
+            if self.zip64 {
+                zip_file.write_all(&self.len.to_le_bytes())?; // compressed len
+                res += 8;
+                zip_file.write_all(&self.size.to_le_bytes())?; // original len
+                res += 8;
+            } else {
+                zip_file.write_all(&(self.len as u32).to_le_bytes())?; // compressed len
+                res += 4;
+                zip_file.write_all(&(self.size as u32).to_le_bytes())?; // original len
+                res += 4;
             }
+            return Ok(res);
         }
         // update crc , save current pos
         let current_pos = zip_file.stream_position()?;
 
         zip_file.seek(std::io::SeekFrom::Start(crc_pos))?;
         zip_file.write_all(&self.crc.get().to_ne_bytes())?;
-        zip_file.write_all(&self.len.to_ne_bytes())?; // compressed len
+        if self.zip64 {
+            zip_file.seek(std::io::SeekFrom::Start(zip64_pos))?;
+            zip_file.write_all(&self.len.to_le_bytes())?; // compressed len
+        } else {
+            zip_file.write_all(&(self.len as u32).to_le_bytes())?; // compressed len
+        }
 
         zip_file.seek(std::io::SeekFrom::Start(current_pos))?;
         Ok(res)
     }
 
-    fn store_dir(&mut self, mut zip_file: &File) -> io::Result<u32> {
+    /// metadata of an external content, a link is not followed for a link entry
+    fn metadata(&self, path: &Path) -> io::Result<fs::Metadata> {
+        if self.link {
+            fs::symlink_metadata(path)
+        } else {
+            fs::metadata(path)
+        }
+    }
+
+    /// modification, access and creation times, explicit times take precedence over times
+    /// of an external content, now is the modification time otherwise
+    fn resolve_times(&self) -> io::Result<[Option<SystemTime>; 3]> {
+        let mut res = self.times;
+        let [mtime, atime, ctime] = &mut res;
+        if let Location::Disk(path) = &self.data {
+            let metadata = self.metadata(path)?;
+            *mtime = mtime.or(metadata.modified().ok());
+            *atime = atime.or(metadata.accessed().ok());
+            *ctime = ctime.or(metadata.created().ok())
+        }
+        *mtime = mtime.or_else(|| Some(SystemTime::now()));
+        Ok(res)
+    }
+
+    /// the resolved modification time
+    fn mtime(&self) -> SystemTime {
+        self.file_times[0].unwrap_or(UNIX_EPOCH)
+    }
+
+    /// UT extra flags of the resolved times
+    #[cfg(any(unix, target_os = "redox"))]
+    fn times_mask(&self) -> u8 {
+        (self.file_times.iter().enumerate())
+            .filter(|(_, time)| time.is_some())
+            .fold(0, |mask, (i, _)| mask | 1 << i)
+    }
+
+    /// Unix mode written in the external attributes, a file type is added when missing
+    fn unix_mode(&self) -> u32 {
+        let mut res = self.mode.unwrap_or(self.st_mode);
+        if res & S_IFMT == 0 {
+            res |= if self.dir {
+                S_IFDIR
+            } else if self.link {
+                S_IFLNK
+            } else {
+                S_IFREG
+            }
+        }
+        if self.attributes.contains(&Attribute::NoWrite) {
+            res &= !0o222
+        }
+        if self.attributes.contains(&Attribute::Exec) {
+            res |= 0o111
+        }
+        res
+    }
+
+    /// a name in the zip, directory names end by a slash
+    fn combined_name(&self) -> String {
+        let mut res = match &self.path {
+            // a root directory has no name
+            Some(path) if self.name.is_empty() => path.to_owned(),
+            Some(path) => path.to_owned() + "/" + &self.name,
+            None => self.name.clone(),
+        };
+        if self.dir {
+            res.push('/')
+        }
+        res
+    }
+
+    /// a compression method value in headers
+    fn method_value(&self) -> u16 {
+        if self.aes_strength > 0 {
+            METHOD_AES
+        } else {
+            self.method.value()
+        }
+    }
+
+    /// writes the content encrypted when a cipher is given,
+    /// returns its length with the encryption overhead and CRC
+    fn write_encrypted(
+        &self,
+        encoder: &mut dyn Encoder,
+        out: &mut dyn Write,
+        cipher: Option<&Cipher>,
+    ) -> io::Result<(u64, u32)> {
+        let (len, crc) = match cipher {
+            Some(&Cipher::ZipCrypto(password, check)) => {
+                let mut out = ZipCrypto::new(password, check, out)?;
+                self.write_content(encoder, &mut out)?
+            }
+            Some(&Cipher::Aes(password, strength)) => {
+                let mut out = Aes::new(password, strength, out)?;
+                let res = self.write_content(encoder, &mut out)?;
+                out.finish()?;
+                res
+            }
+            None => return self.write_content(encoder, out),
+        };
+        Ok((len + cipher.map_or(0, Cipher::overhead), crc))
+    }
+
+    /// compresses the content by the encoder, returns its compressed length and CRC
+    fn write_content(
+        &self,
+        encoder: &mut dyn Encoder,
+        out: &mut dyn Write,
+    ) -> io::Result<(u64, u32)> {
+        let mut crc = 0_u32 /*u32::MAX*/;
+        let mut len = 0;
+        match &self.data {
+            Location::Mem(mem) => {
+                len = encoder.encode_all(mem, out)?;
+                crc = crc32::update_fast_16(crc, mem)
+            }
+            Location::Disk(_) if self.dir => (),
+            Location::Disk(path) if self.link => {
+                let target = link_target(path)?;
+                len = encoder.encode_all(&target, out)?;
+                crc = crc32::update_fast_16(crc, &target)
+            }
+            Location::Disk(path) => {
+                let mut f = File::open(&**path)?;
+                let mut chunk = vec![0_u8; CHUNK_SIZE];
+                let mut size = 0_u64;
+                loop {
+                    let read = match f.read(&mut chunk) {
+                        Ok(0) => break,
+                        Ok(read) => read,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    };
+                    crc = crc32::update_fast_16(crc, &chunk[..read]);
+                    len += encoder.encode(&chunk[..read], out)?;
+                    size += read as u64
+                }
+                len += encoder.finish(out)?;
+                if size != self.size {
+                    return Err(Error::other(format! {"{path:?} changed while zipping"}));
+                }
+            }
+        }
+        Ok((len, crc))
+    }
+
+    fn store_dir<W: Write + Seek>(&mut self, zip_file: &mut W) -> io::Result<u64> {
         let mut res = 0_usize;
+        let zip64_sizes = self.len >= ZIP64_LIMIT || self.size >= ZIP64_LIMIT;
+        let zip64_offset = self.offset >= ZIP64_LIMIT;
+        let ver_extract = if self.zip64 || zip64_sizes || zip64_offset {
+            VER_EXTRACT_ZIP64 // version 4.5
+        } else {
+            VER_EXTRACT // version 2.0
+        }
+        .max(self.method.version())
+        .max(if self.aes_strength > 0 {
+            VER_EXTRACT_AES // version 5.1
+        } else {
+            0
+        });
         zip_file.write_all(&(0x02014b50_u32.to_ne_bytes()))?;
         res += 4;
         zip_file.write_all(&(0x033F_u16.to_ne_bytes()))?; // OS
         res += 2;
-        zip_file.write_all(&VER_EXTRACT.to_ne_bytes())?; // version 2.0
+        zip_file.write_all(&ver_extract.to_ne_bytes())?;
         res += 2;
-        let mut flags = 0_u16;
-        flags |= 1 << 11; // utf8
-        zip_file.write_all(&flags.to_ne_bytes())?; // flags
+        zip_file.write_all(&self.flags.to_ne_bytes())?; // flags
         res += 2;
-        zip_file.write_all(&self.compression.value().to_ne_bytes())?;
+        zip_file.write_all(&self.method_value().to_ne_bytes())?;
         res += 2;
-        res += self.write_common(zip_file)?.0;
-        // TODO reuse previous calculation
-        let combined_name = match &self.path {
-            Some(path) => path.to_owned() + "/" + &self.name,
-            None => self.name.clone(),
+        let crc_sizes = if zip64_sizes {
+            (self.crc.get(), ZIP64_LIMIT as u32, ZIP64_LIMIT as u32)
+        } else {
+            (self.crc.get(), self.len as u32, self.size as u32)
         };
+        res += self.write_common(zip_file, crc_sizes)?.0;
+        let combined_name = self.combined_name();
         let name_bytes = combined_name.as_bytes();
         zip_file.write_all(&(name_bytes.len() as u16).to_ne_bytes())?;
         res += 2;
-        #[cfg(target_os = "windows")]
-        let extra_len = 0_u16; // no extra len
-        #[cfg(any(unix, target_os = "redox"))]
         let mut extra_len = 0_u16;
-        #[cfg(any(unix, target_os = "redox"))]
-        if self.gid != 0 || self.uid != 0 {
-            // ("ux")
-            extra_len += 4 + 1 + 1 + 2 + 1 + 2
+        let mut zip64_len = 0_u16;
+        if zip64_sizes {
+            zip64_len += 8 + 8
         }
+        if zip64_offset {
+            zip64_len += 8
+        }
+        if zip64_len > 0 {
+            extra_len += 2 + 2 + zip64_len
+        }
+        if self.aes_strength > 0 {
+            extra_len += AES_EXTRA.len() as u16 + 1 + 2
+        }
+        let ux = self.owner.map(ux_extra).unwrap_or_default();
+        let ntfs = self.ntfs.map(ntfs_extra).unwrap_or_default();
+        extra_len += (ux.len() + ntfs.len()) as u16;
         #[cfg(any(unix, target_os = "redox"))]
-        if self.modified != 0 || self.created != 0 {
+        {
             // ("UT")
             extra_len += 9
         }
@@ -370,51 +885,60 @@ impl ZipEntry {
         let intern_attr = 0_u16;
         zip_file.write_all(&intern_attr.to_ne_bytes())?; // extra fields
         res += 2;
-        let mut ext_attr = 0x81000000_u32;
-        let mut perm = 0o266_u8;
-        if self.attributes.contains(&Attribute::NoWrite) {
-            perm &= 0o155;
-        }
-        if self.attributes.contains(&Attribute::Exec) {
-            perm |= 0o111;
+        let mut ext_attr = self.unix_mode() << 16;
+        if self.dir {
+            ext_attr |= 0x10; // MS-DOS directory
         }
-        ext_attr |= (perm as u32) << 16;
         zip_file.write_all(&ext_attr.to_ne_bytes())?; // extra fields
         res += 4;
         // no calculation based on multi disks
-        zip_file.write_all(&self.offset.to_ne_bytes())?; // extra fields
+        zip_file.write_all(&(self.offset.min(ZIP64_LIMIT) as u32).to_le_bytes())?;
         res += 4;
         zip_file.write_all(name_bytes)?;
         res += name_bytes.len();
         //  writing extra headers
-        if extra_len > 0 {
-            #[cfg(any(unix, target_os = "redox"))]
-            if self.gid != 0 || self.uid != 0 {
-                // ("ux")
-                zip_file.write_all(&0x7875_u16.to_ne_bytes())?; // uid/gid
-                res += 2;
-                extra_len -= 2;
-                zip_file.write_all(&((1 + 1 + 2 + 1 + 2) as u16).to_ne_bytes())?; // len
-                res += 2;
-                extra_len -= 2;
-                zip_file.write_all(&1_u8.to_ne_bytes())?; // ver
-                res += 1;
-                extra_len -= 1;
-                zip_file.write_all(&2_u8.to_ne_bytes())?; // size
-                res += 1;
-                extra_len -= 1;
-                zip_file.write_all(&(self.uid as u16).to_ne_bytes())?; // uid
-                res += 2;
-                extra_len -= 2;
-                zip_file.write_all(&2_u8.to_ne_bytes())?; // size
-                res += 1;
-                extra_len -= 1;
-                zip_file.write_all(&(self.gid as u16).to_ne_bytes())?; // gid
-                res += 2;
-                extra_len -= 2;
+        if zip64_len > 0 {
+            zip_file.write_all(&0x0001_u16.to_le_bytes())?; // zip64
+            res += 2;
+            extra_len -= 2;
+            zip_file.write_all(&zip64_len.to_le_bytes())?; // len
+            res += 2;
+            extra_len -= 2;
+            if zip64_sizes {
+                zip_file.write_all(&self.size.to_le_bytes())?; // original
+                res += 8;
+                extra_len -= 8;
+                zip_file.write_all(&self.len.to_le_bytes())?; // compressed
+                res += 8;
+                extra_len -= 8;
+            }
+            if zip64_offset {
+                zip_file.write_all(&self.offset.to_le_bytes())?; // the header offset
+                res += 8;
+                extra_len -= 8;
             }
+        }
+        if self.aes_strength > 0 {
+            zip_file.write_all(&AES_EXTRA)?;
+            res += AES_EXTRA.len();
+            extra_len -= AES_EXTRA.len() as u16;
+            zip_file.write_all(&[self.aes_strength])?;
+            res += 1;
+            extra_len -= 1;
+            zip_file.write_all(&self.method.value().to_le_bytes())?; // actual method
+            res += 2;
+            extra_len -= 2;
+        }
+        if extra_len > 0 {
+            // ("ux")
+            zip_file.write_all(&ux)?;
+            res += ux.len();
+            extra_len -= ux.len() as u16;
+            zip_file.write_all(&ntfs)?;
+            res += ntfs.len();
+            extra_len -= ntfs.len() as u16;
             #[cfg(any(unix, target_os = "redox"))]
-            if extra_len > 0 && (self.modified > 0 || self.created > 0) {
+            {
                 // ("UT")
                 // this header appeared if 5455 (UT) present in the file header
                 zip_file.write_all(&0x5455_u16.to_ne_bytes())?; // len
@@ -424,17 +948,11 @@ impl ZipEntry {
                 res += 2;
                 extra_len -= 2;
                 // the below mask has to be in sync with the local header mask
-                zip_file.write_all(&self.times_mask.get().to_ne_bytes())?; // atime, ctime & mtime
+                zip_file.write_all(&self.times_mask().to_ne_bytes())?; // atime, ctime & mtime
                 res += 1;
                 extra_len -= 1;
-                zip_file.write_all(
-                    &((if self.modified > 0 {
-                        self.modified
-                    } else {
-                        self.created
-                    }) as u32)
-                        .to_ne_bytes(),
-                )?; // len
+                // only the modification time is in the central directory
+                zip_file.write_all(&ut_secs(self.mtime()).0.to_ne_bytes())?;
                 res += 4;
                 extra_len -= 4;
             }
@@ -449,71 +967,51 @@ impl ZipEntry {
             zip_file.write_all(comment_bytes)?;
             res += comment_bytes.len();
         }
-        Ok(res as u32)
+        Ok(res as u64)
     }
 
-    fn write_common(&mut self, mut zip_file: &File) -> io::Result<(usize, u64)> {
+    /// writes the header part common for the local and the central directory headers,
+    /// returns the written len and the position of CRC
+    fn write_common<W: Write + Seek>(
+        &mut self,
+        zip_file: &mut W,
+        (crc, len, size): (u32, u32, u32),
+    ) -> io::Result<(usize, u64, u16)> {
         let mut res = 0_usize;
-        let (timezone_offset_min, _dst) = simtime::get_local_timezone_offset_dst();
-        let (y, m, d, h, min, s, _) = match &self.data {
+        match &self.data {
             Location::Mem(_) => {
-                let current = SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap_or_default();
-                self.modified =
-                    (current.as_secs() as i64 + (timezone_offset_min as i64) * 60) as u64;
-                simtime::get_datetime(1970, self.modified)
+                self.st_mode = if self.dir || self.link { 0o777 } else { 0o666 };
             }
             Location::Disk(path) => {
-                let metadata = fs::metadata(path)?;
-                if metadata.permissions().readonly() {
-                    self.attributes.insert(Attribute::NoWrite);
-                }
+                let metadata = self.metadata(path)?;
                 #[cfg(unix)]
-                if metadata.permissions().mode() & 0o111 != 0 {
-                    self.attributes.insert(Attribute::Exec);
+                {
+                    self.st_mode = metadata.mode();
                 }
-                #[cfg(unix)]
+                #[cfg(not(unix))]
                 {
-                    self.uid = metadata.uid();
-                    self.gid = metadata.gid();
-                    self.created = metadata
-                        .created()?
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .map_err(|e| Error::other(format!("because {e}")))?
-                        .as_secs() as _;
+                    self.st_mode = if self.dir || self.link { 0o777 } else { 0o666 };
+                    if metadata.permissions().readonly() {
+                        self.st_mode &= !0o222
+                    }
                 }
-                let timestamp = metadata
-                    .modified()?
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .map_err(|e| Error::other(format!("because {e}")))?;
-                self.modified =
-                    (timestamp.as_secs() as i64 + (timezone_offset_min as i64) * 60) as u64;
-                simtime::get_datetime(1970, self.modified)
             }
         };
-        let time: u16 = ((s >> 1) | (min << 5) | (h << 11)) as u16;
+        let (time, date) = self.dos_datetime;
         zip_file.write_all(&time.to_ne_bytes())?;
         res += 2;
-        let date: u16 = (d + (m << 5) + ((y - 1980) << 9))
-            .try_into()
-            .map_err(|e| Error::other(format!("because {e}")))?;
         zip_file.write_all(&date.to_ne_bytes())?;
         res += 2;
+        // preserve the position to update crc and size after finishing data
         let crc_pos = zip_file.stream_position()?;
-        zip_file.write_all(&(self.crc.get().to_ne_bytes()))?;
+        zip_file.write_all(&(crc.to_ne_bytes()))?;
         res += 4;
-        // preserve the position to update size after finishing data
-        let size_orig = match &self.data {
-            Location::Mem(mem) => mem.len() as _,
-            Location::Disk(path) => fs::metadata(path)?.len(),
-        };
-        zip_file.write_all(&(self.len).to_le_bytes())?;
+        zip_file.write_all(&len.to_le_bytes())?;
         res += 4;
-        zip_file.write_all(&(size_orig as u32).to_le_bytes())?;
+        zip_file.write_all(&size.to_le_bytes())?;
         res += 4;
 
-        Ok((res, crc_pos))
+        Ok((res, crc_pos, time))
     }
 }
 
@@ -562,19 +1060,255 @@ impl ZipInfo {
         }
     }
 
+    /// Adds files of a directory tree, empty directories are added as directory entries
+    ///
+    /// Glob patterns of the options are matched against paths relative to the root
+    /// using slashes, a pattern without a slash matches a name at any level. Nothing is added
+    /// when walking the tree fails
+    pub fn add_dir<P: AsRef<Path>>(&mut self, root: P, options: &DirOptions) -> io::Result<()> {
+        let prefix = options
+            .prefix
+            .as_ref()
+            .map(|prefix| prefix.trim_matches('/'))
+            .filter(|prefix| !prefix.is_empty());
+        let root = root.as_ref();
+        let canonical = fs::canonicalize(root)?;
+        let mut entries = vec![];
+        Self::add_tree(
+            &mut entries,
+            root,
+            &canonical,
+            prefix,
+            "",
+            options,
+            &mut vec![],
+        )?;
+        for entry in entries {
+            self.add(entry);
+        }
+        Ok(())
+    }
+
+    fn add_tree(
+        entries: &mut Vec<ZipEntry>,
+        dir: &Path,
+        root: &Path,
+        zip_path: Option<&str>,
+        rel_path: &str,
+        options: &DirOptions,
+        ignores: &mut Vec<Ignore>,
+    ) -> io::Result<()> {
+        let mut children = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        if children.is_empty() && !rel_path.is_empty() {
+            let parent = zip_path.and_then(|zip_path| zip_path.rsplit_once('/'));
+            entries.push(ZipEntry::from_dir(dir, parent.map(|(parent, _)| parent)));
+            return Ok(());
+        }
+        // keep the zip content reproducible
+        children.sort_by_key(|child| child.file_name());
+        let ignore = Ignore::read(dir, rel_path, &options.ignore_files)?;
+        let pushed = ignore.is_some();
+        ignores.extend(ignore);
+        // directories are walked without following links, so the path is canonical
+        let canonical_dir = root.join(rel_path);
+        for child in children {
+            let name = child.file_name().to_string_lossy().to_string();
+            if !options.hidden && name.starts_with('.') {
+                continue;
+            }
+            let rel_path = if rel_path.is_empty() {
+                name.clone()
+            } else {
+                format!("{rel_path}/{name}")
+            };
+            if options
+                .exclude
+                .iter()
+                .any(|pattern| glob::matches(pattern, &rel_path))
+            {
+                continue;
+            }
+            let path = child.path();
+            let file_type = child.file_type()?;
+            let is_dir = file_type.is_dir();
+            if ignore::ignored(ignores, &rel_path, is_dir) {
+                continue;
+            }
+            if is_dir {
+                let zip_path = match zip_path {
+                    Some(zip_path) => format!("{zip_path}/{name}"),
+                    None => name,
+                };
+                let zip_path = Some(zip_path.as_str());
+                Self::add_tree(entries, &path, root, zip_path, &rel_path, options, ignores)?
+            } else if !options.include.is_empty()
+                && !(options.include.iter()).any(|pattern| glob::matches(pattern, &rel_path))
+            {
+                continue;
+            } else if file_type.is_symlink() && options.symlinks != Symlinks::Follow {
+                // dangling links pointing inside are kept
+                let inside = resolve_link(&path, &canonical_dir)?.starts_with(root);
+                match options.symlinks {
+                    _ if inside => entries.push(ZipEntry::from_symlink(&path, zip_path)),
+                    Symlinks::StoreInside if path.is_file() => {
+                        entries.push(ZipEntry::from_file(&path, zip_path))
+                    }
+                    _ => {
+                        return Err(Error::other(
+                            format! {"{path:?} points outside of the added directory"},
+                        ));
+                    }
+                };
+            } else if path.is_file() {
+                entries.push(ZipEntry::from_file(&path, zip_path));
+            }
+        }
+        if pushed {
+            ignores.pop();
+        }
+        Ok(())
+    }
+
     /// Stores the zip in an external storage
+    ///
+    /// The zip is written in a temporary file zip_name.<8 random digits> first and then renamed
+    /// to the zip name, so a failure keeps a previously stored zip intact
     pub fn store(&mut self) -> io::Result<()> {
-        // consider to create with zip_name.<8 random digits>  and rename to zip_name at the end
-        // use : little-endian byte order
-        let mut zip_file = File::create(&self.zip_name)?;
+        let (temp_name, zip_file) = create_temp(&self.zip_name)?;
+        match self
+            .store_file(zip_file)
+            .and_then(|_| fs::rename(&temp_name, &self.zip_name))
+        {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&temp_name);
+                Err(e)
+            }
+        }
+    }
+
+    fn store_file(&mut self, zip_file: File) -> io::Result<()> {
+        let mut zip_file = BufWriter::new(zip_file);
+        self.store_to(&mut zip_file)?;
+        zip_file
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()
+    }
+
+    /// Stores the zip in a byte vector
+    pub fn to_vec(&mut self) -> io::Result<Vec<u8>> {
+        let mut zip_file = Cursor::new(vec![]);
+        self.store_to(&mut zip_file)?;
+        Ok(zip_file.into_inner())
+    }
+
+    /// Stores the zip in any seekable writer, for example in an already opened file
+    pub fn store_to<W: Write + Seek>(&mut self, zip_file: &mut W) -> io::Result<()> {
+        self.write_zip(zip_file, false)
+    }
+
+    /// Warnings of the last storing, as about clamped DOS dates
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Streams the zip in a not seekable writer, like stdout, a pipe, or a socket
+    ///
+    /// CRC and sizes of entries are written in data descriptors following their content
+    pub fn stream_to<W: Write>(&mut self, zip_file: &mut W) -> io::Result<()> {
+        // headers are written by small fields
+        let mut zip_file = Streamer {
+            inner: BufWriter::new(zip_file),
+            pos: 0,
+        };
+        self.write_zip(&mut zip_file, true)?;
+        zip_file.flush()
+    }
 
+    fn write_zip<W: Write + Seek>(&mut self, zip_file: &mut W, descriptor: bool) -> io::Result<()> {
+        // use : little-endian byte order
+        self.warnings.clear();
+        if self.dos_time == DosTime::Local && !tz::has_rules() {
+            let warning = "no time zone rules are found, DOS times have the current UTC offset";
+            self.warnings.push(warning.to_string())
+        }
         for entry in &mut self.entries {
-            entry.store(&zip_file)?;
+            let compression = match &entry.compression {
+                _ if entry.dir || entry.link => Compression::Store,
+                compression
+                    if *compression == Compression::default()
+                        && !entry.explicit_compression
+                        && self.store_policy.applies(entry) =>
+                {
+                    Compression::Store
+                }
+                compression => compression.clone(),
+            };
+            let level = match entry.level {
+                Some(level) => level,
+                // the zip level applies to all methods, so it's lowered to their maximums
+                None => {
+                    (self.level.unwrap_or(codec::DEFAULT_LEVEL)).min(codec::max_level(&compression))
+                }
+            };
+            let options = StoreOptions {
+                descriptor,
+                compression,
+                level,
+                password: entry
+                    .password
+                    .clone()
+                    .or_else(|| self.password.clone())
+                    .filter(|_| !entry.dir),
+                encryption: entry
+                    .encryption
+                    .clone()
+                    .or_else(|| self.encryption.clone())
+                    .unwrap_or_default(),
+                ownership: self.ownership,
+                ntfs_times: self.ntfs_times,
+                dos_time: self.dos_time,
+            };
+            entry.store(zip_file, options, &mut self.warnings)?;
         }
-        let mut len_central = 0_u32;
+        let mut len_central = 0_u64;
         let offset_central_dir = zip_file.stream_position()?;
         for entry in &mut self.entries {
-            len_central += entry.store_dir(&zip_file)?;
+            len_central += entry.store_dir(zip_file)?;
+        }
+        let entries = self.entries.len() as u64;
+        if entries >= 0xFFFF || len_central >= ZIP64_LIMIT || offset_central_dir >= ZIP64_LIMIT {
+            let offset_zip64_end = zip_file.stream_position()?;
+            // add - zip64 end of central directory record
+            zip_file.write_all(&(0x06064b50_u32.to_le_bytes()))?;
+            // size of the record without leading 12 bytes
+            zip_file.write_all(&(44_u64.to_le_bytes()))?;
+            // version made by
+            zip_file.write_all(&(0x033F_u16.to_le_bytes()))?;
+            // version needed to extract
+            zip_file.write_all(&VER_EXTRACT_ZIP64.to_le_bytes())?;
+            // disk
+            zip_file.write_all(&(0_u32.to_le_bytes()))?;
+            // disk # the dir starts
+            zip_file.write_all(&(0_u32.to_le_bytes()))?;
+            // entries # this disk
+            zip_file.write_all(&entries.to_le_bytes())?;
+            // entries # all
+            zip_file.write_all(&entries.to_le_bytes())?;
+            // len central
+            zip_file.write_all(&len_central.to_le_bytes())?;
+            // offset central
+            zip_file.write_all(&offset_central_dir.to_le_bytes())?;
+
+            // add - zip64 end of central directory locator
+            zip_file.write_all(&(0x07064b50_u32.to_le_bytes()))?;
+            // disk # the zip64 end starts
+            zip_file.write_all(&(0_u32.to_le_bytes()))?;
+            // offset zip64 end
+            zip_file.write_all(&offset_zip64_end.to_le_bytes())?;
+            // disks total
+            zip_file.write_all(&(1_u32.to_le_bytes()))?;
         }
 
         // add - end of central directory record
@@ -584,13 +1318,13 @@ impl ZipInfo {
         // disk # the dir starts
         zip_file.write_all(&(0_u16.to_ne_bytes()))?;
         // entries # this disk
-        zip_file.write_all(&(self.entries.len() as u16).to_ne_bytes())?;
+        zip_file.write_all(&(entries.min(0xFFFF) as u16).to_ne_bytes())?;
         // entries # all
-        zip_file.write_all(&(self.entries.len() as u16).to_ne_bytes())?;
+        zip_file.write_all(&(entries.min(0xFFFF) as u16).to_ne_bytes())?;
         // len central
-        zip_file.write_all(&(len_central.to_ne_bytes()))?;
+        zip_file.write_all(&((len_central.min(ZIP64_LIMIT) as u32).to_ne_bytes()))?;
         // offset central
-        zip_file.write_all(&((offset_central_dir as u32).to_ne_bytes()))?;
+        zip_file.write_all(&((offset_central_dir.min(ZIP64_LIMIT) as u32).to_ne_bytes()))?;
         let comment_bytes = if let Some(comment) = &self.comment {
             comment.as_bytes()
         } else {
@@ -628,15 +1362,298 @@ impl ZipEntry {
         }
     }
 
-    /// Modifies a date of the zip entry
+    /// Creates a directory entry, the name can include parent directories
+    pub fn new_dir(name: impl AsRef<str>) -> ZipEntry {
+        ZipEntry {
+            name: name.as_ref().trim_end_matches('/').into(),
+            dir: true,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a directory entry from an external directory keeping its permissions and times,
+    /// `.` and `..` are named by the directories they refer to, a root directory needs a zip path
+    pub fn from_dir<P: AsRef<Path>>(path: P, zip_path: Option<impl AsRef<str>>) -> ZipEntry {
+        let path = path.as_ref();
+        ZipEntry {
+            name: external_name(path),
+            path: zip_path.map(|s| s.as_ref().into()),
+            data: Disk(path.into()),
+            dir: true,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a symbolic link entry from an external link, the link target is stored as content
+    pub fn from_symlink<P: AsRef<Path>>(path: P, zip_path: Option<impl AsRef<str>>) -> ZipEntry {
+        let path = path.as_ref();
+        ZipEntry {
+            name: external_name(path),
+            path: zip_path.map(|s| s.as_ref().into()),
+            data: Disk(path.into()),
+            link: true,
+            ..Default::default()
+        }
+    }
+
+    /// Sets a modification time of the zip entry
+    pub fn with_mtime(mut self, time: SystemTime) -> Self {
+        self.times[0] = Some(time);
+        self
+    }
+
+    /// Sets an access time of the zip entry
+    pub fn with_atime(mut self, time: SystemTime) -> Self {
+        self.times[1] = Some(time);
+        self
+    }
+
+    /// Sets a creation time of the zip entry
+    pub fn with_ctime(mut self, time: SystemTime) -> Self {
+        self.times[2] = Some(time);
+        self
+    }
+
+    /// Sets a Unix mode of the zip entry, as 0o640 or 0o104755
+    pub fn with_mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Sets a compression level 0-12, 0-22 for zstd, of the zip entry
+    pub fn with_level(mut self, level: u8) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// Sets a compression of the zip entry, the zip store policy doesn't apply then
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self.explicit_compression = true;
+        self
+    }
+
+    /// Sets a password encrypting the zip entry
+    pub fn with_password(mut self, password: impl AsRef<str>) -> Self {
+        self.password = Some(password.as_ref().to_string());
+        self
+    }
+
+    /// Sets an encryption of the zip entry used with a password
+    pub fn with_encryption(mut self, encryption: Encryption) -> Self {
+        self.encryption = Some(encryption);
+        self
+    }
+
+    /// Modifies a date of the zip entry, the same as `with_mtime`
     ///
     /// Now is used otherwise for a vector entry or a file modified date for an external content
-    pub fn created_on(mut self, time: SystemTime) -> Self {
-        self.modified = time
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-        self
+    pub fn created_on(self, time: SystemTime) -> Self {
+        self.with_mtime(time)
+    }
+}
+
+/// Info-ZIP Unix extra of uid and gid ("ux"), an id takes 4 bytes when doesn't fit in 2
+fn ux_extra((uid, gid): (u32, u32)) -> Vec<u8> {
+    let mut res = 0x7875_u16.to_le_bytes().to_vec();
+    res.extend_from_slice(&[0, 0, 1]); // len and version
+    for id in [uid, gid] {
+        match u16::try_from(id) {
+            Ok(id) => {
+                res.push(2);
+                res.extend_from_slice(&id.to_le_bytes())
+            }
+            Err(_) => {
+                res.push(4);
+                res.extend_from_slice(&id.to_le_bytes())
+            }
+        }
+    }
+    let len = (res.len() - 4) as u16;
+    res[2..4].copy_from_slice(&len.to_le_bytes());
+    res
+}
+
+/// seconds since the Unix epoch, negative before it
+fn unix_secs(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs_f64().ceil() as i64),
+    }
+}
+
+/// signed 32 bits seconds of UT extra, true when the time is out of the range and got clamped
+#[cfg(any(unix, target_os = "redox"))]
+fn ut_secs(time: SystemTime) -> (i32, bool) {
+    let secs = unix_secs(time);
+    let clamped = secs.clamp(i32::MIN as i64, i32::MAX as i64);
+    (clamped as i32, clamped != secs)
+}
+
+/// DOS time and date of a time in the time zone, true when the time is out of the DOS
+/// range 1980-2107 and got clamped to it
+fn dos_datetime(time: SystemTime, zone: DosTime) -> ((u16, u16), bool) {
+    // 1980-01-01 and 2108-01-01 in seconds since the epoch
+    static DOS_START: i64 = 315_532_800;
+    static DOS_END: i64 = 4_354_819_200;
+    let secs = unix_secs(time);
+    let local = match zone {
+        DosTime::Local => secs + tz::utc_offset(secs),
+        DosTime::Utc => secs,
+    };
+    let clamped = local.clamp(DOS_START, DOS_END - 1);
+    let (y, m, d, h, min, s, _) = simtime::get_datetime(1970, clamped as u64);
+    let time = (s >> 1) | (min << 5) | (h << 11);
+    let date = d | (m << 5) | ((y - 1980) << 9);
+    ((time as u16, date as u16), clamped != local)
+}
+
+/// 100ns intervals since 1601 of Windows FILETIME
+fn filetime(time: SystemTime) -> u64 {
+    static EPOCH_1601: i128 = 116_444_736_000_000_000;
+    let ticks = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since.as_nanos() / 100) as i128,
+        Err(before) => -((before.duration().as_nanos() / 100) as i128),
+    };
+    (ticks + EPOCH_1601).clamp(0, u64::MAX as i128) as u64
+}
+
+/// NTFS extra of modification, access and creation times
+fn ntfs_extra(times: [u64; 3]) -> Vec<u8> {
+    let mut res = 0x000A_u16.to_le_bytes().to_vec();
+    res.extend_from_slice(&32_u16.to_le_bytes());
+    res.extend_from_slice(&[0; 4]); // reserved
+    res.extend_from_slice(&1_u16.to_le_bytes()); // times tag
+    res.extend_from_slice(&24_u16.to_le_bytes());
+    for time in times {
+        res.extend_from_slice(&time.to_le_bytes())
+    }
+    res
+}
+
+/// reads a link target using slashes
+fn link_target(path: &Path) -> io::Result<Vec<u8>> {
+    let target = fs::read_link(path)?;
+    #[cfg(unix)]
+    return Ok(std::os::unix::ffi::OsStrExt::as_bytes(target.as_os_str()).to_vec());
+    #[cfg(not(unix))]
+    Ok(target.to_string_lossy().replace('\\', "/").into_bytes())
+}
+
+/// resolves a link target relative to the link directory without following links
+fn resolve_link(path: &Path, dir: &Path) -> io::Result<PathBuf> {
+    let mut res = PathBuf::new();
+    for component in dir.join(fs::read_link(path)?).components() {
+        match component {
+            Component::ParentDir => {
+                res.pop();
+            }
+            Component::CurDir => (),
+            component => res.push(component),
+        }
+    }
+    Ok(res)
+}
+
+/// a file name of the path, a name of the canonical path for `.` and `..`, empty for a root
+fn external_name(path: &Path) -> String {
+    let canonical = match path.file_name() {
+        Some(name) => return name.display().to_string(),
+        None => fs::canonicalize(path).unwrap_or_default(),
+    };
+    canonical
+        .file_name()
+        .map(|name| name.display().to_string())
+        .unwrap_or_default()
+}
+
+/// Creates a not existing file zip_name.<8 random digits> next to the zip
+fn create_temp(zip_name: &Path) -> io::Result<(PathBuf, File)> {
+    for _ in 0..16 {
+        let random = RandomState::new().build_hasher().finish() % 100_000_000;
+        let mut temp_name = zip_name.as_os_str().to_owned();
+        temp_name.push(format!(".{random:08}"));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_name)
+        {
+            Ok(file) => return Ok((temp_name.into(), file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(Error::new(
+        io::ErrorKind::AlreadyExists,
+        format! {"can't create a temporary file for {zip_name:?}"},
+    ))
+}
+
+/// Tracks a position in a not seekable writer
+struct Streamer<W: Write> {
+    inner: W,
+    pos: u64,
+}
+
+impl<W: Write> Write for Streamer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.pos += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Seek for Streamer<W> {
+    /// only the current position can be obtained
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match pos {
+            SeekFrom::Current(0) => Ok(self.pos),
+            _ => Err(Error::new(
+                io::ErrorKind::Unsupported,
+                "a stream can't be seeked",
+            )),
+        }
+    }
+}
+
+/// Options of storing an entry resolved from the entry and the zip
+struct StoreOptions {
+    /// CRC and sizes are written in a data descriptor after the content,
+    /// otherwise they get updated in the header
+    descriptor: bool,
+    compression: Compression,
+    level: u8,
+    password: Option<String>,
+    encryption: Encryption,
+    ownership: Ownership,
+    ntfs_times: bool,
+    dos_time: DosTime,
+}
+
+/// Refuses writing over a limit, so a content can be rewritten in the same room
+struct Limited<'a> {
+    inner: &'a mut dyn Write,
+    left: u64,
+    exceeded: bool,
+}
+
+impl Write for Limited<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 > self.left {
+            self.exceeded = true;
+            return Err(Error::other("the limit is exceeded"));
+        }
+        let written = self.inner.write(buf)?;
+        self.left -= written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
     }
 }
 
@@ -15,6 +15,30 @@ test.zip"#
         "test content".as_bytes().to_vec(),
     ));
     zip.add(simzip::ZipEntry::from_file("README.md", Some("doc")));
+    // compressible entries of every method and encryption, the test target checks those
+    // unzip doesn't support by bsdtar
+    for (name, compression) in [
+        ("bzip2", simzip::Compression::BZIP2),
+        ("lzma", simzip::Compression::LZMA),
+        ("xz", simzip::Compression::Xz),
+        ("zstd", simzip::Compression::Zstd),
+    ] {
+        zip.add(
+            simzip::ZipEntry::new(format!("codecs/{name}.txt"), content(name))
+                .with_compression(compression),
+        );
+    }
+    for (name, encryption) in [
+        ("zipcrypto", simzip::Encryption::ZipCrypto),
+        ("aes128", simzip::Encryption::Aes128),
+        ("aes256", simzip::Encryption::Aes256),
+    ] {
+        zip.add(
+            simzip::ZipEntry::new(format!("codecs/{name}.txt"), content(name))
+                .with_password("simzip")
+                .with_encryption(encryption),
+        );
+    }
     #[cfg(feature = "test_unix")]
     {
         zip.add(simzip::ZipEntry::from_file("run script.sh", Some("test")));
@@ -24,4 +48,40 @@ test.zip"#
         Ok(()) => println! {"Zipped good!"},
         Err(msg) => println! {"zip error: {msg}"},
     }
+    match zip.to_vec() {
+        Ok(bytes) => println! {"Zipped in memory {} bytes", bytes.len()},
+        Err(msg) => println! {"zip error: {msg}"},
+    }
+    // the same entries followed by data descriptors
+    match std::fs::File::create("stream.zip").and_then(|mut file| zip.stream_to(&mut file)) {
+        Ok(()) => println! {"Streamed good!"},
+        Err(msg) => println! {"stream error: {msg}"},
+    }
+    // a directory tree, the link of the unix test is stored as a link
+    let mut tree = simzip::ZipInfo::new("tree.zip");
+    let options = simzip::DirOptions {
+        include: [
+            "/src/*.rs",
+            "/*.md",
+            "/run script.sh",
+            "/read only.txt",
+            "/test link",
+        ]
+        .map(String::from)
+        .to_vec(),
+        ignore_files: vec![".gitignore".to_string()],
+        symlinks: simzip::Symlinks::Store,
+        ..Default::default()
+    };
+    match tree.add_dir(".", &options).and_then(|()| tree.store()) {
+        Ok(()) => println! {"Zipped tree good!"},
+        Err(msg) => println! {"tree error: {msg}"},
+    }
+}
+
+/// a line of a repeating text
+fn content(name: &str) -> Vec<u8> {
+    let mut res = format!("test content of {name} ").repeat(10).into_bytes();
+    res.push(b'\n');
+    res
 }
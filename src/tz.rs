@@ -0,0 +1,420 @@
+//! UTC offsets of the local time zone at given timestamps
+//!
+//! Rules are read from the `TZ` variable, /etc/localtime in TZif format, or the Windows time
+//! zone settings, the current offset reported by simtime is used when there are no rules
+use std::{env, fs, path::Path, sync::OnceLock};
+
+/// a day of a daylight saving time change in a POSIX TZ string
+#[derive(Clone, Copy)]
+enum Day {
+    /// `Jn`, 1-365 not counting February 29
+    Julian(i64),
+    /// `n`, 0-365 counting February 29
+    Ordinal(i64),
+    /// `Mm.w.d`, a weekday of a week of a month, the week 5 is the last one
+    Month(i64, i64, i64),
+}
+
+/// Daylight saving time of a POSIX TZ string
+struct Dst {
+    /// UTC offset in seconds
+    offset: i64,
+    /// days and times of the start in the standard time and of the end in the daylight time
+    start: (Day, i64),
+    end: (Day, i64),
+}
+
+/// A POSIX TZ string as `CET-1CEST,M3.5.0,M10.5.0/3`
+struct Rule {
+    /// UTC offset of the standard time in seconds
+    std: i64,
+    dst: Option<Dst>,
+}
+
+struct TimeZone {
+    /// transition times and UTC offsets since them
+    transitions: Vec<(i64, i64)>,
+    /// an offset before the first transition
+    initial: i64,
+    /// a rule after the last transition
+    rule: Option<Rule>,
+}
+
+/// UTC offset in seconds of the local time at the timestamp in seconds since the epoch, the
+/// current offset is used for all timestamps when the time zone has no rules
+pub(crate) fn utc_offset(secs: i64) -> i64 {
+    match zone() {
+        Some(zone) => zone.offset(secs),
+        None => {
+            let (offset_min, dst) = simtime::get_local_timezone_offset_dst();
+            offset_min as i64 * 60 + if dst { 3600 } else { 0 }
+        }
+    }
+}
+
+/// checks if rules of the local time zone are known
+pub(crate) fn has_rules() -> bool {
+    zone().is_some()
+}
+
+fn zone() -> Option<&'static TimeZone> {
+    static ZONE: OnceLock<Option<TimeZone>> = OnceLock::new();
+    ZONE.get_or_init(load).as_ref()
+}
+
+fn load() -> Option<TimeZone> {
+    let tz = env::var("TZ").unwrap_or_default();
+    if tz.is_empty() {
+        #[cfg(target_os = "windows")]
+        return windows::load();
+        #[cfg(not(target_os = "windows"))]
+        return fs::read("/etc/localtime")
+            .ok()
+            .and_then(|data| parse_tzif(&data));
+    }
+    let tz = tz.strip_prefix(':').unwrap_or(&tz);
+    let path = Path::new("/usr/share/zoneinfo").join(tz);
+    fs::read(path)
+        .ok()
+        .and_then(|data| parse_tzif(&data))
+        .or_else(|| {
+            Some(TimeZone {
+                transitions: vec![],
+                initial: 0,
+                rule: Some(parse_rule(tz)?),
+            })
+        })
+}
+
+impl TimeZone {
+    fn offset(&self, secs: i64) -> i64 {
+        let after = self.transitions.partition_point(|&(time, _)| time <= secs);
+        match &self.rule {
+            Some(rule) if after == self.transitions.len() => rule.offset(secs),
+            _ if after == 0 => self.initial,
+            _ => self.transitions[after - 1].1,
+        }
+    }
+}
+
+impl Rule {
+    fn offset(&self, secs: i64) -> i64 {
+        let Some(dst) = &self.dst else {
+            return self.std;
+        };
+        let year = civil_year((secs + self.std).div_euclid(86400));
+        let start = change(dst.start, year) - self.std;
+        let end = change(dst.end, year) - dst.offset;
+        let in_dst = if start < end {
+            start <= secs && secs < end
+        } else {
+            // the southern hemisphere
+            !(end <= secs && secs < start)
+        };
+        if in_dst { dst.offset } else { self.std }
+    }
+}
+
+/// local seconds since the epoch of a change in the year
+fn change((day, time): (Day, i64), year: i64) -> i64 {
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let days = match day {
+        Day::Julian(n) => days_from_civil(year, 1, 1) + n - 1 + (leap && n >= 60) as i64,
+        Day::Ordinal(n) => days_from_civil(year, 1, 1) + n,
+        Day::Month(month, week, weekday) => {
+            let first = days_from_civil(year, month, 1);
+            let next = if month == 12 {
+                days_from_civil(year + 1, 1, 1)
+            } else {
+                days_from_civil(year, month + 1, 1)
+            };
+            // January 1, 1970 is Thursday
+            let mut res = first + (weekday - (first + 4)).rem_euclid(7) + (week - 1) * 7;
+            while res >= next {
+                res -= 7
+            }
+            res
+        }
+    };
+    days * 86400 + time
+}
+
+/// days since the epoch of a date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// a year of days since the epoch
+fn civil_year(days: i64) -> i64 {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let month = (5 * doy + 2) / 153;
+    yoe + era * 400 + (month >= 10) as i64
+}
+
+fn parse_tzif(data: &[u8]) -> Option<TimeZone> {
+    // isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt
+    let counts = |data: &[u8]| -> Option<[usize; 6]> {
+        if data.get(..4)? != b"TZif" {
+            return None;
+        }
+        let mut res = [0; 6];
+        for (i, count) in data.get(20..44)?.chunks_exact(4).enumerate() {
+            res[i] = u32::from_be_bytes(count.try_into().unwrap()) as usize
+        }
+        Some(res)
+    };
+    let block_len = |[isut, isstd, leap, time, types, chars]: [usize; 6], time_len: usize| {
+        time * (time_len + 1) + types * 6 + chars + leap * (time_len + 4) + isstd + isut
+    };
+    let mut data = data;
+    let mut count = counts(data)?;
+    let version = *data.get(4)?;
+    let mut time_len = 4;
+    if version >= b'2' {
+        // the second header with 64 bits times follows the first block
+        data = data.get(44 + block_len(count, 4)..)?;
+        count = counts(data)?;
+        time_len = 8
+    }
+    let [_, _, _, time_count, type_count, _] = count;
+    let block = data.get(44..44 + block_len(count, time_len))?;
+    let (times, rest) = block.split_at(time_count * time_len);
+    let (indices, rest) = rest.split_at(time_count);
+    let offsets: Vec<i64> = rest[..type_count * 6]
+        .chunks_exact(6)
+        .map(|ttinfo| i32::from_be_bytes(ttinfo[..4].try_into().unwrap()) as i64)
+        .collect();
+    let transitions = times
+        .chunks_exact(time_len)
+        .zip(indices)
+        .map(|(time, &index)| {
+            let time = match time_len {
+                4 => i32::from_be_bytes(time.try_into().unwrap()) as i64,
+                _ => i64::from_be_bytes(time.try_into().unwrap()),
+            };
+            Some((time, *offsets.get(index as usize)?))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    // a TZ string footer of the second version
+    let rule = match data.get(44 + block.len()..) {
+        Some([b'\n', footer @ ..]) if version >= b'2' => {
+            let end = footer.iter().position(|&b| b == b'\n')?;
+            parse_rule(std::str::from_utf8(&footer[..end]).ok()?)
+        }
+        _ => None,
+    };
+    Some(TimeZone {
+        transitions,
+        initial: *offsets.first()?,
+        rule,
+    })
+}
+
+/// parses a POSIX TZ string, offsets in it are west of Greenwich
+fn parse_rule(tz: &str) -> Option<Rule> {
+    let mut tz = tz.as_bytes();
+    skip_name(&mut tz)?;
+    let std = -parse_time(&mut tz)?;
+    if tz.is_empty() {
+        return Some(Rule { std, dst: None });
+    }
+    skip_name(&mut tz)?;
+    let offset = match tz.first() {
+        Some(b'0'..=b'9' | b'+' | b'-') => -parse_time(&mut tz)?,
+        _ => std + 3600,
+    };
+    // the US rule is the default
+    let (start, end) = match tz.split_first() {
+        None => ((Day::Month(3, 2, 0), 7200), (Day::Month(11, 1, 0), 7200)),
+        Some((b',', rest)) => {
+            tz = rest;
+            let start = parse_change(&mut tz)?;
+            tz = tz.strip_prefix(b",")?;
+            (start, parse_change(&mut tz)?)
+        }
+        _ => return None,
+    };
+    Some(Rule {
+        std,
+        dst: Some(Dst { offset, start, end }),
+    })
+}
+
+/// skips an alphabetic or a quoted as `<+03>` name
+fn skip_name(tz: &mut &[u8]) -> Option<()> {
+    let len = match tz.first()? {
+        b'<' => tz.iter().position(|&b| b == b'>')? + 1,
+        _ => tz.iter().take_while(|b| b.is_ascii_alphabetic()).count(),
+    };
+    *tz = tz.get(len..).filter(|_| len > 0)?;
+    Some(())
+}
+
+/// parses `[+-]hh[:mm[:ss]]` in seconds
+fn parse_time(tz: &mut &[u8]) -> Option<i64> {
+    let sign = match tz.first()? {
+        b'-' => -1,
+        _ => 1,
+    };
+    if let Some(b'+' | b'-') = tz.first() {
+        *tz = &tz[1..]
+    }
+    let mut res = 0;
+    for (i, unit) in [3600, 60, 1].into_iter().enumerate() {
+        if i > 0 {
+            match tz.strip_prefix(b":") {
+                Some(rest) => *tz = rest,
+                None => break,
+            }
+        }
+        res += parse_number(tz)? * unit
+    }
+    Some(sign * res)
+}
+
+/// parses a change as `M3.5.0/3`, the change time is 2:00 by default
+fn parse_change(tz: &mut &[u8]) -> Option<(Day, i64)> {
+    let day = match tz.first()? {
+        b'J' => {
+            *tz = &tz[1..];
+            Day::Julian(parse_number(tz)?)
+        }
+        b'M' => {
+            *tz = &tz[1..];
+            let month = parse_number(tz)?;
+            *tz = tz.strip_prefix(b".")?;
+            let week = parse_number(tz)?;
+            *tz = tz.strip_prefix(b".")?;
+            Day::Month(month, week, parse_number(tz)?)
+        }
+        _ => Day::Ordinal(parse_number(tz)?),
+    };
+    let time = match tz.strip_prefix(b"/") {
+        Some(rest) => {
+            *tz = rest;
+            parse_time(tz)?
+        }
+        None => 7200,
+    };
+    Some((day, time))
+}
+
+fn parse_number(tz: &mut &[u8]) -> Option<i64> {
+    let len = tz.iter().take_while(|b| b.is_ascii_digit()).count();
+    let res = std::str::from_utf8(&tz[..len]).ok()?.parse().ok()?;
+    *tz = &tz[len..];
+    Some(res)
+}
+
+/// Rules of the Windows time zone settings, they can differ by years
+#[cfg(target_os = "windows")]
+mod windows {
+    use super::{Day, Dst, Rule, TimeZone, change, days_from_civil};
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct SystemTime {
+        year: u16,
+        month: u16,
+        day_of_week: u16,
+        day: u16,
+        hour: u16,
+        minute: u16,
+        second: u16,
+        milliseconds: u16,
+    }
+
+    /// TIME_ZONE_INFORMATION, biases are in minutes west of Greenwich
+    #[repr(C)]
+    #[derive(Default)]
+    struct TimeZoneInformation {
+        bias: i32,
+        standard_name: [u16; 32],
+        standard_date: SystemTime,
+        standard_bias: i32,
+        daylight_name: [u16; 32],
+        daylight_date: SystemTime,
+        daylight_bias: i32,
+    }
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn GetTimeZoneInformationForYear(
+            year: u16,
+            dynamic: *const u8,
+            info: *mut TimeZoneInformation,
+        ) -> i32;
+    }
+
+    /// the years of DOS times with a year of a margin
+    const YEARS: std::ops::RangeInclusive<i64> = 1979..=2108;
+
+    /// transitions of the current time zone by rules of each year of DOS times
+    pub(super) fn load() -> Option<TimeZone> {
+        let mut rules: Vec<(i64, Rule)> = YEARS
+            .map(|year| Some((year, rule(year)?)))
+            .collect::<Option<_>>()?;
+        let mut transitions = vec![];
+        for (year, rule) in &rules {
+            // the standard offset can change by years too
+            let start = days_from_civil(*year, 1, 1) * 86400 - rule.std;
+            transitions.push((start, rule.offset(start)));
+            if let Some(dst) = &rule.dst {
+                transitions.push((change(dst.start, *year) - rule.std, dst.offset));
+                transitions.push((change(dst.end, *year) - dst.offset, rule.std))
+            }
+        }
+        transitions.sort_by_key(|&(time, _)| time);
+        Some(TimeZone {
+            initial: transitions.first()?.1,
+            transitions,
+            rule: Some(rules.pop()?.1),
+        })
+    }
+
+    fn rule(year: i64) -> Option<Rule> {
+        let mut info = TimeZoneInformation::default();
+        // SAFETY: the info is a valid TIME_ZONE_INFORMATION, null means the current time zone
+        if unsafe { GetTimeZoneInformationForYear(year as u16, std::ptr::null(), &mut info) } == 0 {
+            return None;
+        }
+        let std = -(info.bias + info.standard_bias) as i64 * 60;
+        // no daylight saving time in the year
+        if info.daylight_date.month == 0 {
+            return Some(Rule { std, dst: None });
+        }
+        Some(Rule {
+            std,
+            dst: Some(Dst {
+                offset: -(info.bias + info.daylight_bias) as i64 * 60,
+                start: day_time(&info.daylight_date, year),
+                end: day_time(&info.standard_date, year),
+            }),
+        })
+    }
+
+    /// a change of a zero year is a weekday of a week of the month, the week 5 is the last one,
+    /// otherwise it's a date
+    fn day_time(date: &SystemTime, year: i64) -> (Day, i64) {
+        let (month, day) = (date.month as i64, date.day as i64);
+        let day = match date.year {
+            0 => Day::Month(month, day, date.day_of_week as i64),
+            _ => Day::Ordinal(days_from_civil(year, month, day) - days_from_civil(year, 1, 1)),
+        };
+        // changes at 23:59:59.999 are rounded to the midnight
+        let time = date.hour as i64 * 3600
+            + date.minute as i64 * 60
+            + date.second as i64
+            + (date.milliseconds as i64 + 500) / 1000;
+        (day, time)
+    }
+}
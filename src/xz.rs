@@ -0,0 +1,171 @@
+//! XZ container of LZMA2 compressed data
+use crate::codec::Encoder;
+use crate::crc32;
+use crate::lzma::{LzmaCore, PROPS, RangeEncoder};
+use std::io::{self, Write};
+
+const MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0];
+const FOOTER_MAGIC: [u8; 2] = [b'Y', b'Z'];
+/// no flags and CRC32 check
+const STREAM_FLAGS: [u8; 2] = [0, 1];
+const LZMA2_ID: u8 = 0x21;
+/// limits of LZMA2 chunks
+const MAX_UNPACKED: usize = 1 << 21;
+const MAX_PACKED: usize = 1 << 16;
+/// a room for a symbol and the range encoder flush
+const PACKED_MARGIN: usize = 64;
+
+/// a variable length integer of XZ
+fn multibyte(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7
+    }
+    out.push(value as u8)
+}
+
+/// pads by zeros to a multiple of 4
+fn pad(out: &mut Vec<u8>) {
+    out.resize(out.len().next_multiple_of(4), 0)
+}
+
+/// Compresses a content coming by chunks in a single block XZ stream
+pub(crate) struct Xz {
+    core: LzmaCore,
+    started: bool,
+    /// CRC32 of uncompressed data
+    crc: u32,
+    size: u64,
+    /// a size of LZMA2 chunks
+    packed: u64,
+    need_dict_reset: bool,
+    need_props: bool,
+    need_state_reset: bool,
+}
+
+impl Xz {
+    pub(crate) fn new(level: u8) -> Self {
+        Xz {
+            core: LzmaCore::new(level),
+            started: false,
+            crc: 0,
+            size: 0,
+            packed: 0,
+            need_dict_reset: true,
+            need_props: true,
+            need_state_reset: false,
+        }
+    }
+
+    /// the stream header and the block header
+    fn header(&self) -> Vec<u8> {
+        let mut res = MAGIC.to_vec();
+        res.extend_from_slice(&STREAM_FLAGS);
+        res.extend_from_slice(&crc32::update_fast_16(0, &STREAM_FLAGS).to_le_bytes());
+        // no sizes in the block header as they aren't known yet
+        let dict_bits = self.core.dict_size().trailing_zeros();
+        let mut block = vec![0, 0, LZMA2_ID, 1, (2 * (dict_bits - 12)) as u8];
+        pad(&mut block);
+        block[0] = (block.len() / 4) as u8;
+        block.extend_from_slice(&crc32::update_fast_16(0, &block).to_le_bytes());
+        res.extend_from_slice(&block);
+        res
+    }
+
+    /// encodes a next LZMA2 chunk of data till `end` at most
+    fn chunk(&mut self, end: usize, out: &mut dyn Write) -> io::Result<u64> {
+        let mut res = 0;
+        if !self.started {
+            let header = self.header();
+            out.write_all(&header)?;
+            res += header.len() as u64;
+            self.started = true
+        }
+        if self.need_state_reset {
+            self.core.reset_state()
+        }
+        let unpacked = self
+            .core
+            .encode(end.min(MAX_UNPACKED), MAX_PACKED - PACKED_MARGIN);
+        let mut rc = std::mem::replace(&mut self.core.rc, RangeEncoder::new());
+        rc.flush();
+        let mut chunk = vec![];
+        if rc.out.len() < unpacked {
+            let reset = if self.need_dict_reset {
+                3
+            } else if self.need_props {
+                2
+            } else if self.need_state_reset {
+                1
+            } else {
+                0
+            };
+            chunk.push(0x80 | reset << 5 | ((unpacked - 1) >> 16) as u8);
+            chunk.extend_from_slice(&((unpacked - 1) as u16).to_be_bytes());
+            chunk.extend_from_slice(&((rc.out.len() - 1) as u16).to_be_bytes());
+            if reset >= 2 {
+                chunk.push(PROPS)
+            }
+            chunk.extend_from_slice(&rc.out);
+            self.need_props = false;
+            self.need_state_reset = false
+        } else {
+            // stored, the encoder state has to be reset as the decoder doesn't see it
+            for data in self.core.encoded(unpacked).chunks(MAX_PACKED) {
+                chunk.push(if self.need_dict_reset { 1 } else { 2 });
+                chunk.extend_from_slice(&((data.len() - 1) as u16).to_be_bytes());
+                chunk.extend_from_slice(data);
+                self.need_dict_reset = false
+            }
+            self.need_state_reset = true
+        }
+        self.need_dict_reset = false;
+        out.write_all(&chunk)?;
+        self.packed += chunk.len() as u64;
+        Ok(res + chunk.len() as u64)
+    }
+}
+
+impl Encoder for Xz {
+    fn encode(&mut self, chunk: &[u8], out: &mut dyn Write) -> io::Result<u64> {
+        self.crc = crc32::update_fast_16(self.crc, chunk);
+        self.size += chunk.len() as u64;
+        self.core.append(chunk);
+        let mut res = 0;
+        while self.core.available() >= MAX_UNPACKED {
+            res += self.chunk(MAX_UNPACKED, out)?
+        }
+        Ok(res)
+    }
+
+    fn finish(&mut self, out: &mut dyn Write) -> io::Result<u64> {
+        let mut res = 0;
+        while self.core.available() > 0 || !self.started {
+            res += self.chunk(self.core.available(), out)?
+        }
+        // the end of LZMA2 data, the block padding and the check
+        let mut tail = vec![0];
+        self.packed += 1;
+        tail.resize(
+            tail.len() + (self.packed.next_multiple_of(4) - self.packed) as usize,
+            0,
+        );
+        tail.extend_from_slice(&self.crc.to_le_bytes());
+        // the index of the single block
+        let mut index = vec![0];
+        multibyte(1, &mut index);
+        multibyte(12 + self.packed + 4, &mut index);
+        multibyte(self.size, &mut index);
+        pad(&mut index);
+        index.extend_from_slice(&crc32::update_fast_16(0, &index).to_le_bytes());
+        tail.extend_from_slice(&index);
+        // the stream footer
+        let mut footer = ((index.len() / 4 - 1) as u32).to_le_bytes().to_vec();
+        footer.extend_from_slice(&STREAM_FLAGS);
+        tail.extend_from_slice(&crc32::update_fast_16(0, &footer).to_le_bytes());
+        tail.extend_from_slice(&footer);
+        tail.extend_from_slice(&FOOTER_MAGIC);
+        out.write_all(&tail)?;
+        Ok(res + tail.len() as u64)
+    }
+}
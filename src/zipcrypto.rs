@@ -0,0 +1,69 @@
+//! Traditional PKWARE encryption
+use crate::crctabl::CRC32_TABLE;
+use crate::random;
+use std::io::{self, Write};
+
+/// a length of the encryption header preceding the content
+pub(crate) const HEADER_LEN: u64 = 12;
+
+fn crc32(crc: u32, b: u8) -> u32 {
+    (crc >> 8) ^ CRC32_TABLE[0][((crc ^ b as u32) & 0xFF) as usize]
+}
+
+/// Encrypts everything written in the inner writer
+pub(crate) struct ZipCrypto<'a> {
+    keys: [u32; 3],
+    inner: &'a mut dyn Write,
+    buf: Vec<u8>,
+}
+
+impl<'a> ZipCrypto<'a> {
+    /// writes the encryption header ending by the check byte, so the writer is ready
+    /// for the content
+    pub(crate) fn new(password: &str, check: u8, inner: &'a mut dyn Write) -> io::Result<Self> {
+        let mut res = ZipCrypto {
+            keys: [0x1234_5678, 0x2345_6789, 0x3456_7890],
+            inner,
+            buf: vec![],
+        };
+        for &b in password.as_bytes() {
+            res.update(b)
+        }
+        let mut header = [0_u8; HEADER_LEN as usize];
+        random::fill(&mut header[..11])?;
+        header[11] = check;
+        res.write_all(&header)?;
+        Ok(res)
+    }
+
+    fn update(&mut self, b: u8) {
+        self.keys[0] = crc32(self.keys[0], b);
+        self.keys[1] = (self.keys[1].wrapping_add(self.keys[0] & 0xFF))
+            .wrapping_mul(134_775_813)
+            .wrapping_add(1);
+        self.keys[2] = crc32(self.keys[2], (self.keys[1] >> 24) as u8)
+    }
+
+    fn encrypt(&mut self, b: u8) -> u8 {
+        let temp = (self.keys[2] | 2) & 0xFFFF;
+        let key = (temp.wrapping_mul(temp ^ 1) >> 8) as u8;
+        self.update(b);
+        b ^ key
+    }
+}
+
+impl Write for ZipCrypto<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut encrypted = std::mem::take(&mut self.buf);
+        encrypted.clear();
+        encrypted.extend(buf.iter().map(|&b| self.encrypt(b)));
+        // the keys are already updated, so all has to be written
+        let res = self.inner.write_all(&encrypted);
+        self.buf = encrypted;
+        res.map(|_| buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,809 @@
+//! Pure Rust Zstandard encoder
+use crate::codec::Encoder;
+use crate::huffman;
+use std::io::{self, Write};
+
+/// the maximum compression level of zstd
+pub(crate) const MAX_LEVEL: u8 = 22;
+const MAGIC: u32 = 0xFD2F_B528;
+const BLOCK_SIZE: usize = 128 * 1024;
+/// the shortest match searched, the format allows 3
+const MIN_MATCH: usize = 4;
+const HASH_BITS: u32 = 17;
+const MAX_HUFFMAN_BITS: u8 = 11;
+/// accuracy logs of weights, literal lengths, match lengths and offsets tables
+const WEIGHTS_LOG: u32 = 6;
+const LL_MAX_LOG: u32 = 9;
+const ML_MAX_LOG: u32 = 9;
+const OF_MAX_LOG: u32 = 8;
+
+static LL_BASE: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32, 40, 48, 64,
+    128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536,
+];
+static LL_BITS: [u32; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11,
+    12, 13, 14, 15, 16,
+];
+static LL_DEFAULT: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1,
+];
+static ML_BASE: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27,
+    28, 29, 30, 31, 32, 33, 34, 35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027,
+    2051, 4099, 8195, 16387, 32771, 65539,
+];
+static ML_BITS: [u32; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+static ML_DEFAULT: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+static OF_DEFAULT: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+
+fn highbit(value: u32) -> u32 {
+    31 - value.leading_zeros()
+}
+
+/// Writes bits from the least significant, zstd reads such streams from the end
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u32, len: u32) {
+        self.bits |= ((value as u64) & ((1 << len) - 1)) << self.count;
+        self.count += len;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8
+        }
+    }
+
+    /// pads the last byte by zero bits
+    fn align(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8)
+        }
+        self.out
+    }
+
+    /// marks the end of a stream read backward
+    fn close(mut self) -> Vec<u8> {
+        self.put(1, 1);
+        self.align()
+    }
+}
+
+/// Finite state entropy coding table
+struct Fse {
+    log: u32,
+    states: Vec<u16>,
+    /// a number of bits delta and a state delta of every symbol
+    symbols: Vec<(u32, i32)>,
+}
+
+impl Fse {
+    /// builds the table from normalized counts, -1 is a less than 1 probability
+    fn new(norm: &[i16], log: u32) -> Self {
+        let size = 1_usize << log;
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut high = size - 1;
+        let mut cumul = vec![0_usize; norm.len() + 1];
+        let mut spread = vec![0_usize; size];
+        for (s, &n) in norm.iter().enumerate() {
+            if n == -1 {
+                cumul[s + 1] = cumul[s] + 1;
+                spread[high] = s;
+                high -= 1
+            } else {
+                cumul[s + 1] = cumul[s] + n as usize
+            }
+        }
+        let mut pos = 0;
+        for (s, &n) in norm.iter().enumerate() {
+            for _ in 0..n.max(0) {
+                spread[pos] = s;
+                loop {
+                    pos = (pos + step) & (size - 1);
+                    if pos <= high {
+                        break;
+                    }
+                }
+            }
+        }
+        let mut states = vec![0_u16; size];
+        for (u, &s) in spread.iter().enumerate() {
+            states[cumul[s]] = (size + u) as u16;
+            cumul[s] += 1
+        }
+        let mut total = 0_i32;
+        let symbols = norm
+            .iter()
+            .map(|&n| match n {
+                0 => (((log + 1) << 16) - size as u32, 0),
+                -1 | 1 => {
+                    total += 1;
+                    ((log << 16) - size as u32, total - 2)
+                }
+                _ => {
+                    let n = n as u32;
+                    let max_bits = log - highbit(n - 1);
+                    let delta = total - n as i32;
+                    total += n as i32;
+                    ((max_bits << 16) - (n << max_bits), delta)
+                }
+            })
+            .collect();
+        Fse {
+            log,
+            states,
+            symbols,
+        }
+    }
+
+    fn init(&self, symbol: u8) -> u32 {
+        let (delta_bits, delta_state) = self.symbols[symbol as usize];
+        let bits = (delta_bits + (1 << 15)) >> 16;
+        let value = (bits << 16) - delta_bits;
+        self.states[((value >> bits) as i32 + delta_state) as usize] as u32
+    }
+
+    fn encode(&self, bits: &mut BitWriter, state: &mut u32, symbol: u8) {
+        let (delta_bits, delta_state) = self.symbols[symbol as usize];
+        let len = (*state + delta_bits) >> 16;
+        bits.put(*state, len);
+        *state = self.states[((*state >> len) as i32 + delta_state) as usize] as u32
+    }
+
+    fn flush(&self, bits: &mut BitWriter, state: u32) {
+        bits.put(state, self.log)
+    }
+}
+
+/// Scales frequencies to a sum of 2^log keeping every present symbol
+fn normalize(freqs: &[u32], log: u32) -> Vec<i16> {
+    let size = 1_i32 << log;
+    let total: u64 = freqs.iter().map(|&f| f as u64).sum();
+    let mut norm: Vec<i16> = freqs
+        .iter()
+        .map(|&f| match f {
+            0 => 0,
+            _ => ((f as u64 * size as u64 + total / 2) / total).max(1) as i16,
+        })
+        .collect();
+    let mut sum: i32 = norm.iter().map(|&n| n as i32).sum();
+    while sum != size {
+        let largest = (0..norm.len()).max_by_key(|&s| norm[s]).unwrap();
+        let diff = (size - sum).max(1 - norm[largest] as i32);
+        norm[largest] += diff as i16;
+        sum += diff
+    }
+    let used = norm.iter().rposition(|&n| n != 0).map_or(0, |s| s + 1);
+    norm.truncate(used);
+    norm
+}
+
+/// a table accuracy log fitting a number of symbols
+fn table_log(total: usize, distinct: usize, max_log: u32) -> u32 {
+    let log = (usize::BITS - total.leading_zeros()).clamp(5, max_log);
+    (log..max_log)
+        .find(|&log| 1 << log >= 2 * distinct)
+        .unwrap_or(max_log)
+}
+
+/// an approximate size in bits of symbols coded by the table
+fn cost(freqs: &[u32], norm: &[i16], log: u32) -> Option<f64> {
+    let mut bits = 0.0;
+    for (s, &f) in freqs.iter().enumerate() {
+        if f > 0 {
+            let n = *norm.get(s)?;
+            if n == 0 {
+                return None;
+            }
+            bits += f as f64 * (log as f64 - (n.abs() as f64).log2())
+        }
+    }
+    Some(bits)
+}
+
+/// writes the normalized counts in the table description format
+fn write_norm(norm: &[i16], log: u32) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    bits.put(log - 5, 4);
+    let mut remaining = (1_i32 << log) + 1;
+    let mut threshold = 1_i32 << log;
+    let mut len = log + 1;
+    let mut symbol = 0;
+    let mut previous_zero = false;
+    while symbol < norm.len() && remaining > 1 {
+        if previous_zero {
+            let mut start = symbol;
+            while norm[symbol] == 0 {
+                symbol += 1
+            }
+            while symbol >= start + 24 {
+                start += 24;
+                bits.put(0xFFFF, 16)
+            }
+            while symbol >= start + 3 {
+                start += 3;
+                bits.put(3, 2)
+            }
+            bits.put((symbol - start) as u32, 2)
+        }
+        let mut count = norm[symbol] as i32;
+        symbol += 1;
+        let max = 2 * threshold - 1 - remaining;
+        remaining -= count.abs();
+        count += 1;
+        if count >= threshold {
+            count += max
+        }
+        bits.put(count as u32, len - (count < max) as u32);
+        previous_zero = count == 1;
+        while remaining < threshold {
+            len -= 1;
+            threshold >>= 1
+        }
+    }
+    bits.align()
+}
+
+/// A coding of literal lengths, match lengths or offsets codes in a block
+enum Table {
+    Rle,
+    Fse(Fse),
+}
+
+impl Table {
+    /// chooses the cheapest mode and writes its description, returns the mode and the table
+    fn new(
+        codes: &[u8],
+        default: &[i16],
+        default_log: u32,
+        max_log: u32,
+        out: &mut Vec<u8>,
+    ) -> (u8, Table) {
+        let mut freqs = vec![0_u32; default.len()];
+        for &c in codes {
+            freqs[c as usize] += 1
+        }
+        let distinct = freqs.iter().filter(|&&f| f > 0).count();
+        if distinct == 1 {
+            out.push(codes[0]);
+            return (1, Table::Rle);
+        }
+        let log = table_log(codes.len(), distinct, max_log);
+        let norm = normalize(&freqs, log);
+        let description = write_norm(&norm, log);
+        let custom = cost(&freqs, &norm, log).unwrap() + 8.0 * description.len() as f64;
+        match cost(&freqs, default, default_log) {
+            Some(predefined) if predefined <= custom => {
+                (0, Table::Fse(Fse::new(default, default_log)))
+            }
+            _ => {
+                out.extend_from_slice(&description);
+                (2, Table::Fse(Fse::new(&norm, log)))
+            }
+        }
+    }
+
+    fn init(&self, symbol: u8) -> u32 {
+        match self {
+            Table::Rle => 0,
+            Table::Fse(fse) => fse.init(symbol),
+        }
+    }
+
+    fn encode(&self, bits: &mut BitWriter, state: &mut u32, symbol: u8) {
+        if let Table::Fse(fse) = self {
+            fse.encode(bits, state, symbol)
+        }
+    }
+
+    fn flush(&self, bits: &mut BitWriter, state: u32) {
+        if let Table::Fse(fse) = self {
+            fse.flush(bits, state)
+        }
+    }
+}
+
+/// A match preceded by literals
+struct Sequence {
+    literals: u32,
+    len: u32,
+    /// an offset + 3, or 1 for the last offset
+    offset: u32,
+}
+
+/// a code and extra bits of a value
+fn code(base: &[u32], value: u32) -> (u8, u32) {
+    let code = base.partition_point(|&b| b <= value) - 1;
+    (code as u8, value - base[code])
+}
+
+fn literals_header(kind: u8, size: usize, out: &mut Vec<u8>) {
+    if size < 32 {
+        out.push(kind | (size << 3) as u8)
+    } else if size < 4096 {
+        out.extend_from_slice(&[kind | 1 << 2 | (size << 4) as u8, (size >> 4) as u8])
+    } else {
+        out.extend_from_slice(&[
+            kind | 3 << 2 | (size << 4) as u8,
+            (size >> 4) as u8,
+            (size >> 12) as u8,
+        ])
+    }
+}
+
+/// encodes Huffman weights by two interleaved FSE states
+fn compress_weights(weights: &[u8]) -> Option<Vec<u8>> {
+    let mut freqs = [0_u32; MAX_HUFFMAN_BITS as usize + 1];
+    for &w in weights {
+        freqs[w as usize] += 1
+    }
+    // a single symbol can't be coded as its count would be unknown
+    if freqs.iter().filter(|&&f| f > 0).count() < 2 {
+        return None;
+    }
+    let norm = normalize(&freqs, WEIGHTS_LOG);
+    let fse = Fse::new(&norm, WEIGHTS_LOG);
+    let mut out = write_norm(&norm, WEIGHTS_LOG);
+    let mut bits = BitWriter::default();
+    let mut states = [None, None];
+    for (i, &w) in weights.iter().enumerate().rev() {
+        match &mut states[i & 1] {
+            Some(state) => fse.encode(&mut bits, state, w),
+            state => *state = Some(fse.init(w)),
+        }
+    }
+    fse.flush(&mut bits, states[1]?);
+    fse.flush(&mut bits, states[0]?);
+    out.extend_from_slice(&bits.close());
+    (out.len() < 128).then_some(out)
+}
+
+fn huffman_stream(literals: &[u8], codes: &[(u32, u32)]) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    for &b in literals.iter().rev() {
+        let (code, len) = codes[b as usize];
+        bits.put(code, len)
+    }
+    bits.close()
+}
+
+/// writes the literals section, Huffman compressed when it's smaller
+fn write_literals(literals: &[u8], out: &mut Vec<u8>) {
+    let mut freqs = [0_u32; 256];
+    for &b in literals {
+        freqs[b as usize] += 1
+    }
+    match freqs.iter().filter(|&&f| f > 0).count() {
+        0 => return literals_header(0, 0, out),
+        1 => {
+            literals_header(1, literals.len(), out);
+            return out.push(literals[0]);
+        }
+        _ => (),
+    }
+    if let Some(compressed) = huffman_literals(literals, &freqs)
+        && compressed.len() < literals.len()
+    {
+        return out.extend_from_slice(&compressed);
+    }
+    literals_header(0, literals.len(), out);
+    out.extend_from_slice(literals)
+}
+
+fn huffman_literals(literals: &[u8], freqs: &[u32]) -> Option<Vec<u8>> {
+    let lengths = huffman::code_lengths(freqs, MAX_HUFFMAN_BITS);
+    let max_bits = *lengths.iter().max()?;
+    let last = lengths.iter().rposition(|&l| l > 0)?;
+    let weights: Vec<u8> = lengths[..last]
+        .iter()
+        .map(|&l| if l > 0 { max_bits + 1 - l } else { 0 })
+        .collect();
+    let mut tree = vec![];
+    if weights.len() <= 128 {
+        tree.push(127 + weights.len() as u8);
+        for pair in weights.chunks(2) {
+            tree.push(pair[0] << 4 | pair.get(1).unwrap_or(&0))
+        }
+    } else {
+        let compressed = compress_weights(&weights)?;
+        tree.push(compressed.len() as u8);
+        tree.extend_from_slice(&compressed)
+    }
+    // canonical codes, the longest get the smallest values
+    let mut codes = vec![(0_u32, 0_u32); 256];
+    let mut next = 0_u32;
+    for len in (1..=max_bits).rev() {
+        for (s, _) in lengths.iter().enumerate().filter(|&(_, &l)| l == len) {
+            codes[s] = (next, len as u32);
+            next += 1
+        }
+        next >>= 1
+    }
+    let mut body = tree;
+    let single = literals.len() <= 1023;
+    if single {
+        body.extend_from_slice(&huffman_stream(literals, &codes))
+    } else {
+        let segment = literals.len().div_ceil(4);
+        let streams: Vec<Vec<u8>> = literals
+            .chunks(segment)
+            .map(|chunk| huffman_stream(chunk, &codes))
+            .collect();
+        for stream in &streams[..3] {
+            body.extend_from_slice(&(stream.len() as u16).to_le_bytes())
+        }
+        for stream in &streams {
+            body.extend_from_slice(stream)
+        }
+    }
+    let size = literals.len().max(body.len());
+    let (format, len) = match size {
+        _ if single => (0, 10),
+        ..1024 => (1, 10),
+        1024..16384 => (2, 14),
+        _ => (3, 18),
+    };
+    if body.len() >= 1 << len {
+        return None;
+    }
+    let header = 2 | format << 2 | (literals.len() as u64) << 4 | (body.len() as u64) << (4 + len);
+    let mut out = header.to_le_bytes()[..(4 + 2 * len as usize) / 8].to_vec();
+    out.extend_from_slice(&body);
+    Some(out)
+}
+
+fn write_sequences(sequences: &[Sequence], out: &mut Vec<u8>) {
+    let n = sequences.len();
+    match n {
+        ..128 => out.push(n as u8),
+        128..0x7F00 => out.extend_from_slice(&[(n >> 8) as u8 + 0x80, n as u8]),
+        _ => {
+            out.push(0xFF);
+            out.extend_from_slice(&((n - 0x7F00) as u16).to_le_bytes())
+        }
+    }
+    if n == 0 {
+        return;
+    }
+    let ll: Vec<(u8, u32)> = sequences
+        .iter()
+        .map(|s| code(&LL_BASE, s.literals))
+        .collect();
+    let ml: Vec<(u8, u32)> = sequences.iter().map(|s| code(&ML_BASE, s.len)).collect();
+    let of: Vec<(u8, u32)> = sequences
+        .iter()
+        .map(|s| (highbit(s.offset) as u8, s.offset))
+        .collect();
+    let modes = out.len();
+    out.push(0);
+    let codes: Vec<u8> = ll.iter().map(|c| c.0).collect();
+    let (ll_mode, ll_table) = Table::new(&codes, &LL_DEFAULT, 6, LL_MAX_LOG, out);
+    let codes: Vec<u8> = of.iter().map(|c| c.0).collect();
+    let (of_mode, of_table) = Table::new(&codes, &OF_DEFAULT, 5, OF_MAX_LOG, out);
+    let codes: Vec<u8> = ml.iter().map(|c| c.0).collect();
+    let (ml_mode, ml_table) = Table::new(&codes, &ML_DEFAULT, 6, ML_MAX_LOG, out);
+    out[modes] = ll_mode << 6 | of_mode << 4 | ml_mode << 2;
+    // sequences are coded from the last as the decoder reads the stream backward
+    let mut bits = BitWriter::default();
+    let mut ml_state = ml_table.init(ml[n - 1].0);
+    let mut of_state = of_table.init(of[n - 1].0);
+    let mut ll_state = ll_table.init(ll[n - 1].0);
+    for i in (0..n).rev() {
+        if i < n - 1 {
+            of_table.encode(&mut bits, &mut of_state, of[i].0);
+            ml_table.encode(&mut bits, &mut ml_state, ml[i].0);
+            ll_table.encode(&mut bits, &mut ll_state, ll[i].0)
+        }
+        bits.put(ll[i].1, LL_BITS[ll[i].0 as usize]);
+        bits.put(ml[i].1, ML_BITS[ml[i].0 as usize]);
+        bits.put(of[i].1, of[i].0 as u32)
+    }
+    ml_table.flush(&mut bits, ml_state);
+    of_table.flush(&mut bits, of_state);
+    ll_table.flush(&mut bits, ll_state);
+    out.extend_from_slice(&bits.close())
+}
+
+/// Match search parameters of a compression level
+struct Params {
+    window_log: u32,
+    /// the maximum number of chain entries to check
+    chain: usize,
+    /// a match length stopping the search
+    nice: usize,
+    /// check the next position for a better match
+    lazy: bool,
+}
+
+/// every parameter only grows with the level, chains are limited by the window size,
+/// the level 0 means the default level 3 as in zstd
+fn params(level: u8) -> Params {
+    let (window_log, chain, nice, lazy) = match level {
+        1 => (19, 1, 16, false),
+        2 => (19, 2, 24, false),
+        0 | 3 => (20, 4, 32, true),
+        4 => (20, 8, 32, true),
+        5 => (21, 8, 48, true),
+        6 => (21, 16, 64, true),
+        7 => (21, 24, 64, true),
+        8 => (22, 32, 96, true),
+        9 => (22, 48, 128, true),
+        10 => (22, 64, 128, true),
+        11 => (22, 80, 160, true),
+        12 => (23, 96, 192, true),
+        13 => (23, 112, 224, true),
+        14 => (23, 128, 256, true),
+        15 => (23, 144, 256, true),
+        16 => (23, 160, 320, true),
+        17 => (23, 192, 384, true),
+        18 => (23, 224, 448, true),
+        19 => (23, 256, 512, true),
+        20 => (23, 256, 1024, true),
+        21 => (23, 256, 2048, true),
+        _ => (23, 256, 4096, true),
+    };
+    Params {
+        window_log,
+        chain: chain.min(1 << (window_log - 15)),
+        nice,
+        lazy,
+    }
+}
+
+/// a gain of a match as 4 bits per byte less offset bits, as zstd estimates it
+fn gain((len, dist): (usize, usize), rep: usize) -> i64 {
+    let offset = if dist == rep { 1 } else { dist + 3 };
+    len as i64 * 4 - offset.ilog2() as i64
+}
+
+/// Compresses a content coming by chunks in a Zstandard frame
+pub(crate) struct Zstd {
+    params: Params,
+    /// the window followed by not compressed yet data
+    buf: Vec<u8>,
+    /// an absolute position of `buf[0]`
+    base: u64,
+    /// an index in `buf` of not compressed yet data
+    start: usize,
+    /// last absolute positions of hashes
+    head: Vec<u32>,
+    /// previous absolute positions with the same hash
+    prev: Vec<u32>,
+    /// repeated offsets
+    reps: [u32; 3],
+    started: bool,
+}
+
+impl Zstd {
+    pub(crate) fn new(level: u8) -> Self {
+        let params = params(level);
+        let window = 1 << params.window_log;
+        Zstd {
+            params,
+            buf: vec![],
+            base: 0,
+            start: 0,
+            head: vec![u32::MAX; 1 << HASH_BITS],
+            prev: vec![u32::MAX; window],
+            reps: [1, 4, 8],
+            started: false,
+        }
+    }
+
+    fn hash(&self, i: usize) -> usize {
+        let b = u32::from_le_bytes(self.buf[i..i + 4].try_into().unwrap());
+        (b.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, i: usize) {
+        if i + 4 <= self.buf.len() {
+            let h = self.hash(i);
+            let pos = (self.base + i as u64) as u32;
+            let slot = pos as usize & (self.prev.len() - 1);
+            self.prev[slot] = self.head[h];
+            self.head[h] = pos
+        }
+    }
+
+    /// a length of data at `i` matching data `dist` back
+    fn match_len(&self, i: usize, dist: usize, max_len: usize) -> usize {
+        let (a, b) = (&self.buf[i - dist..][..max_len], &self.buf[i..][..max_len]);
+        let mut len = 0;
+        // by 8 bytes
+        while len + 8 <= max_len {
+            let word = |data: &[u8]| u64::from_le_bytes(data[len..len + 8].try_into().unwrap());
+            let diff = word(a) ^ word(b);
+            if diff != 0 {
+                return len + (diff.trailing_zeros() / 8) as usize;
+            }
+            len += 8
+        }
+        len + a[len..]
+            .iter()
+            .zip(&b[len..])
+            .take_while(|(a, b)| a == b)
+            .count()
+    }
+
+    /// a distance back from `i` available for matches
+    fn history(&self, i: usize) -> usize {
+        ((self.base + i as u64) as usize).min(self.prev.len())
+    }
+
+    /// finds the longest match, returns its (len, dist)
+    fn find_match(&self, i: usize, max_len: usize) -> (usize, usize) {
+        let mut best = (0, 0);
+        if max_len < MIN_MATCH {
+            return best;
+        }
+        let history = self.history(i) as u32;
+        let pos = (self.base + i as u64) as u32;
+        let mut candidate = self.head[self.hash(i)];
+        let mut last_dist = 0;
+        for _ in 0..self.params.chain {
+            if candidate == u32::MAX {
+                break;
+            }
+            let dist = pos.wrapping_sub(candidate);
+            if dist == 0 || dist > history || dist <= last_dist {
+                break;
+            }
+            last_dist = dist;
+            let dist = dist as usize;
+            if self.buf[i - dist + best.0] == self.buf[i + best.0] {
+                let len = self.match_len(i, dist, max_len);
+                if len > best.0 {
+                    best = (len, dist);
+                    if len >= self.params.nice || len == max_len {
+                        break;
+                    }
+                }
+            }
+            candidate = self.prev[candidate as usize & (self.prev.len() - 1)]
+        }
+        if best.0 < MIN_MATCH { (0, 0) } else { best }
+    }
+
+    /// splits data of the next block to literals and sequences
+    fn parse(&mut self, end: usize) -> (Vec<u8>, Vec<Sequence>) {
+        let mut literals = vec![];
+        let mut sequences = vec![];
+        let mut i = self.start;
+        let mut literal_start = i;
+        // a match found by the lazy check of the previous position
+        let mut next = None;
+        while i + MIN_MATCH <= end {
+            let max_len = end - i;
+            let mut best = next.take().unwrap_or_else(|| self.find_match(i, max_len));
+            let rep = self.reps[0] as usize;
+            if i > literal_start && rep <= self.history(i) {
+                let len = self.match_len(i, rep, max_len);
+                if len >= MIN_MATCH && len + 1 >= best.0 {
+                    best = (len, rep)
+                }
+            }
+            self.insert(i);
+            let (len, dist) = best;
+            if len == 0 {
+                i += 1;
+                continue;
+            }
+            if self.params.lazy && len < self.params.nice {
+                let following = self.find_match(i + 1, max_len - 1);
+                // the rep offset code needs literals
+                let current_rep = if i > literal_start { rep } else { 0 };
+                if following.0 > 0 && gain(following, rep) > gain(best, current_rep) + 4 {
+                    i += 1;
+                    next = Some(following);
+                    continue;
+                }
+            }
+            literals.extend_from_slice(&self.buf[literal_start..i]);
+            let offset = if i > literal_start && dist == rep {
+                1
+            } else {
+                self.reps = [dist as u32, self.reps[0], self.reps[1]];
+                dist as u32 + 3
+            };
+            sequences.push(Sequence {
+                literals: (i - literal_start) as u32,
+                len: len as u32,
+                offset,
+            });
+            for j in i + 1..i + len {
+                self.insert(j)
+            }
+            i += len;
+            literal_start = i
+        }
+        literals.extend_from_slice(&self.buf[literal_start..end]);
+        (literals, sequences)
+    }
+
+    /// writes a block of the size, returns a number of written bytes
+    fn block(&mut self, size: usize, last: bool, out: &mut dyn Write) -> io::Result<u64> {
+        let mut res = 0;
+        if !self.started {
+            out.write_all(&MAGIC.to_le_bytes())?;
+            // no content size and checksum, the window descriptor follows
+            out.write_all(&[0, ((self.params.window_log - 10) << 3) as u8])?;
+            res += 6;
+            self.started = true
+        }
+        let end = self.start + size;
+        let first = self.buf.get(self.start).copied();
+        let (kind, body) =
+            if size > 0 && self.buf[self.start..end].iter().all(|&b| Some(b) == first) {
+                for i in self.start..end {
+                    self.insert(i)
+                }
+                (1, vec![self.buf[self.start]])
+            } else {
+                let reps = self.reps;
+                let (literals, sequences) = self.parse(end);
+                let mut body = vec![];
+                write_literals(&literals, &mut body);
+                write_sequences(&sequences, &mut body);
+                if body.len() < size {
+                    (2, body)
+                } else {
+                    // the decoder doesn't see the sequences
+                    self.reps = reps;
+                    (0, self.buf[self.start..end].to_vec())
+                }
+            };
+        let header =
+            last as u32 | kind << 1 | (if kind == 1 { size } else { body.len() } as u32) << 3;
+        out.write_all(&header.to_le_bytes()[..3])?;
+        out.write_all(&body)?;
+        res += 3 + body.len() as u64;
+        self.start = end;
+        Ok(res)
+    }
+}
+
+impl Encoder for Zstd {
+    fn encode(&mut self, chunk: &[u8], out: &mut dyn Write) -> io::Result<u64> {
+        let window = self.prev.len();
+        if self.start > 2 * window {
+            let shift = self.start - window;
+            self.buf.drain(..shift);
+            self.base += shift as u64;
+            self.start -= shift
+        }
+        self.buf.extend_from_slice(chunk);
+        let mut res = 0;
+        // the last block is written at the finish
+        while self.buf.len() - self.start > BLOCK_SIZE {
+            res += self.block(BLOCK_SIZE, false, out)?
+        }
+        Ok(res)
+    }
+
+    fn finish(&mut self, out: &mut dyn Write) -> io::Result<u64> {
+        let mut res = 0;
+        loop {
+            let size = (self.buf.len() - self.start).min(BLOCK_SIZE);
+            let last = self.start + size == self.buf.len();
+            res += self.block(size, last, out)?;
+            if last {
+                return Ok(res);
+            }
+        }
+    }
+}